@@ -0,0 +1,596 @@
+//! Keyframed parameter animations, with blending and crossfading between clips.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+
+use crate::params::{get_axis, set_axis, ParamAxis};
+
+/// How a keyframe's value moves towards the next keyframe.
+#[derive(Debug, Clone, Copy)]
+pub enum Interpolation {
+    /// Holds the value until the next keyframe.
+    Step,
+    Linear,
+    /// Eases with a cubic bezier curve, given like a CSS `cubic-bezier(x1, y1, x2, y2)`.
+    Bezier([f32; 4]),
+}
+
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: f32,
+    pub interpolation: Interpolation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub param: String,
+    pub axis: ParamAxis,
+    /// Sorted by time.
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn sample(&self, t: f32) -> Option<f32> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if t <= first.time {
+            return Some(first.value);
+        }
+        if t >= last.time {
+            return Some(last.value);
+        }
+
+        let next = self.keyframes.partition_point(|k| k.time <= t);
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let u = (t - a.time) / (b.time - a.time);
+
+        let u = match a.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => u,
+            Interpolation::Bezier(curve) => cubic_bezier(curve, u),
+        };
+        Some(a.value + u * (b.value - a.value))
+    }
+}
+
+/// A set of tracks played together, e.g. a reaction.
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub name: String,
+    pub duration: f32,
+    pub tracks: Vec<Track>,
+}
+
+impl Clip {
+    /// Parses a clip from JSON:
+    ///
+    /// ```json
+    /// {
+    ///     "name": "nod",
+    ///     "duration": 1.0,
+    ///     "tracks": [{
+    ///         "param": "Head:: Yaw-Pitch",
+    ///         "axis": "y",
+    ///         "keyframes": [
+    ///             { "time": 0, "value": 0, "interpolation": "bezier", "ease": [0.42, 0, 0.58, 1] },
+    ///             { "time": 0.5, "value": -1, "interpolation": "linear" },
+    ///             { "time": 1, "value": 0 }
+    ///         ]
+    ///     }]
+    /// }
+    /// ```
+    ///
+    /// `duration` defaults to the time of the last keyframe.
+    pub fn from_json(src: &str) -> anyhow::Result<Self> {
+        let root = json::parse(src)?;
+
+        let tracks = root["tracks"]
+            .members()
+            .map(|track| {
+                let param = track["param"]
+                    .as_str()
+                    .context("track without a param")?
+                    .to_owned();
+                let axis = ParamAxis::parse(track["axis"].as_str().unwrap_or("x"))?;
+
+                let mut keyframes = track["keyframes"]
+                    .members()
+                    .map(parse_keyframe)
+                    .collect::<anyhow::Result<Vec<_>>>()
+                    .with_context(|| format!("invalid keyframe in track {param:?}"))?;
+                keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+                Ok(Track {
+                    param,
+                    axis,
+                    keyframes,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let last_key = tracks
+            .iter()
+            .filter_map(|track| track.keyframes.last())
+            .map(|key| key.time)
+            .fold(0.0, f32::max);
+
+        Ok(Self {
+            name: root["name"].as_str().unwrap_or_default().to_owned(),
+            duration: root["duration"].as_f32().unwrap_or(last_key),
+            tracks,
+        })
+    }
+}
+
+fn parse_keyframe(key: &json::JsonValue) -> anyhow::Result<Keyframe> {
+    let time = key["time"].as_f32().context("keyframe without a time")?;
+    let value = key["value"].as_f32().context("keyframe without a value")?;
+
+    let interpolation = match key["interpolation"].as_str().unwrap_or("linear") {
+        "step" => Interpolation::Step,
+        "linear" => Interpolation::Linear,
+        "bezier" => {
+            let ease = &key["ease"];
+            let mut curve = [0.0; 4];
+            for (i, c) in curve.iter_mut().enumerate() {
+                *c = ease[i].as_f32().context("bezier ease needs 4 numbers")?;
+            }
+            Interpolation::Bezier(curve)
+        }
+        other => return Err(anyhow!("unknown interpolation {other:?}")),
+    };
+
+    Ok(Keyframe {
+        time,
+        value,
+        interpolation,
+    })
+}
+
+/// Evaluates a CSS-style easing curve at `x` in [0, 1].
+fn cubic_bezier([x1, y1, x2, y2]: [f32; 4], x: f32) -> f32 {
+    let bezier = |a: f32, b: f32, s: f32| {
+        let r = 1.0 - s;
+        3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+    };
+    let slope = |a: f32, b: f32, s: f32| {
+        let r = 1.0 - s;
+        3.0 * r * r * a + 6.0 * r * s * (b - a) + 3.0 * s * s * (1.0 - b)
+    };
+
+    // Find the curve parameter for x with Newton's method, then bisection if it stalls
+    let mut s = x;
+    for _ in 0..8 {
+        let dx = bezier(x1, x2, s) - x;
+        let d = slope(x1, x2, s);
+        if dx.abs() < 1e-5 || d.abs() < 1e-6 {
+            break;
+        }
+        s = (s - dx / d).clamp(0.0, 1.0);
+    }
+    if (bezier(x1, x2, s) - x).abs() >= 1e-5 {
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..20 {
+            s = (lo + hi) / 2.0;
+            if bezier(x1, x2, s) < x {
+                lo = s;
+            } else {
+                hi = s;
+            }
+        }
+    }
+
+    bezier(y1, y2, s)
+}
+
+/// Playback state of a clip.
+pub struct ClipPlayer {
+    pub clip: Clip,
+    /// Position in the clip, in seconds.
+    pub time: f32,
+    pub speed: f32,
+    pub looping: bool,
+    pub playing: bool,
+    /// Influence of the clip over the puppet's parameters, from 0 to 1.
+    pub weight: f32,
+    target_weight: f32,
+    /// Weight change per second while fading.
+    fade_rate: f32,
+}
+
+impl ClipPlayer {
+    pub fn new(clip: Clip) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            looping: true,
+            playing: false,
+            weight: 1.0,
+            target_weight: 1.0,
+            fade_rate: 0.0,
+        }
+    }
+
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.clip.duration);
+    }
+
+    pub fn set_weight(&mut self, weight: f32) {
+        self.weight = weight.clamp(0.0, 1.0);
+        self.target_weight = self.weight;
+    }
+
+    /// Moves the weight towards `weight` over `duration` seconds.
+    pub fn fade_to(&mut self, weight: f32, duration: f32) {
+        self.target_weight = weight.clamp(0.0, 1.0);
+        if duration > 0.0 {
+            self.fade_rate = (self.target_weight - self.weight).abs() / duration;
+        } else {
+            self.weight = self.target_weight;
+        }
+    }
+
+    fn update(&mut self, dt: f32) {
+        if self.playing {
+            self.time += dt * self.speed;
+
+            let duration = self.clip.duration;
+            // Only the end in the direction of play ends it, a clip starting at 0 isn't over
+            let ended = if self.speed < 0.0 {
+                self.time <= 0.0
+            } else {
+                self.time >= duration
+            };
+            if self.looping && duration > 0.0 {
+                self.time = self.time.rem_euclid(duration);
+            } else if ended {
+                self.time = self.time.clamp(0.0, duration);
+                self.playing = false;
+            }
+        }
+
+        let step = self.fade_rate * dt;
+        if (self.target_weight - self.weight).abs() <= step {
+            self.weight = self.target_weight;
+        } else {
+            self.weight += step.copysign(self.target_weight - self.weight);
+        }
+    }
+}
+
+/// Mixes every loaded clip into the puppet's parameters.
+#[derive(Default)]
+pub struct Animator {
+    pub players: Vec<ClipPlayer>,
+}
+
+impl Animator {
+    /// Adds a stopped clip, returning its index.
+    pub fn add(&mut self, clip: Clip) -> usize {
+        self.players.push(ClipPlayer::new(clip));
+        self.players.len() - 1
+    }
+
+    pub fn player_mut(&mut self, index: usize) -> anyhow::Result<&mut ClipPlayer> {
+        self.players
+            .get_mut(index)
+            .with_context(|| format!("no animation with index {index}"))
+    }
+
+    /// Plays a clip and fades it in over `duration` seconds, while fading out every other clip.
+    pub fn crossfade(&mut self, index: usize, duration: f32) -> anyhow::Result<()> {
+        let player = self.player_mut(index)?;
+        player.playing = true;
+        // Restart one-shot clips that already ended
+        if !player.looping {
+            if player.speed < 0.0 && player.time <= 0.0 {
+                player.time = player.clip.duration;
+            } else if player.speed >= 0.0 && player.time >= player.clip.duration {
+                player.time = 0.0;
+            }
+        }
+
+        for (i, player) in self.players.iter_mut().enumerate() {
+            player.fade_to(if i == index { 1.0 } else { 0.0 }, duration);
+        }
+        Ok(())
+    }
+
+    /// Whether any clip is playing or fading, so that the puppet changes over time.
+    pub fn is_active(&self) -> bool {
+        self.players
+            .iter()
+            .any(|p| (p.playing && p.weight > 0.0) || p.weight != p.target_weight)
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for player in &mut self.players {
+            player.update(dt);
+        }
+    }
+
+    /// Blends every clip over the values already in `values` (or the parameters' defaults).
+    pub fn write(&self, puppet: &Puppet, values: &mut HashMap<String, Vec2>) {
+        let blended = self.blend(|param, axis| get_axis(puppet, values, param, axis));
+        for ((param, axis), value) in blended {
+            set_axis(puppet, values, param, axis, value);
+        }
+    }
+
+    /// Blends every clip over the values `base` gives, skipping parameters it has none for.
+    fn blend(
+        &self,
+        base: impl Fn(&str, ParamAxis) -> Option<f32>,
+    ) -> HashMap<(&str, ParamAxis), f32> {
+        let mut blended: HashMap<(&str, ParamAxis), (f32, f32, f32)> = HashMap::new();
+
+        for player in self.players.iter().filter(|p| p.weight > 0.0) {
+            for track in &player.clip.tracks {
+                let Some(value) = track.sample(player.time) else {
+                    continue;
+                };
+                let Some(base) = base(&track.param, track.axis) else {
+                    continue;
+                };
+
+                let (_, offset, weights) = blended
+                    .entry((track.param.as_str(), track.axis))
+                    .or_insert((base, 0.0, 0.0));
+                *offset += player.weight * (value - base);
+                *weights += player.weight;
+            }
+        }
+
+        blended
+            .into_iter()
+            // Weights below 1 in total blend with what was there before, above they are normalized
+            .map(|(key, (base, offset, weights))| (key, base + offset / weights.max(1.0)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(time: f32, value: f32, interpolation: Interpolation) -> Keyframe {
+        Keyframe {
+            time,
+            value,
+            interpolation,
+        }
+    }
+
+    fn track(param: &str, keyframes: Vec<Keyframe>) -> Track {
+        Track {
+            param: param.to_owned(),
+            axis: ParamAxis::X,
+            keyframes,
+        }
+    }
+
+    /// A one second clip moving `param` from `from` to `to`.
+    fn ramp(param: &str, from: f32, to: f32) -> Clip {
+        Clip {
+            name: param.to_owned(),
+            duration: 1.0,
+            tracks: vec![track(
+                param,
+                vec![
+                    key(0.0, from, Interpolation::Linear),
+                    key(1.0, to, Interpolation::Linear),
+                ],
+            )],
+        }
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn samples_step_and_linear_keyframes() {
+        let t = track(
+            "p",
+            vec![
+                key(1.0, 0.0, Interpolation::Step),
+                key(2.0, 10.0, Interpolation::Linear),
+                key(4.0, 20.0, Interpolation::Linear),
+            ],
+        );
+
+        assert_eq!(t.sample(0.0), Some(0.0));
+        assert_eq!(t.sample(1.5), Some(0.0));
+        assert_eq!(t.sample(2.0), Some(10.0));
+        assert_eq!(t.sample(3.0), Some(15.0));
+        assert_eq!(t.sample(9.0), Some(20.0));
+        assert_eq!(track("p", vec![]).sample(0.0), None);
+    }
+
+    #[test]
+    fn bezier_matches_reference_curves() {
+        // Reference from bisection in f64, far beyond the precision needed
+        let reference = |[x1, y1, x2, y2]: [f32; 4], x: f32| {
+            let bezier = |a: f64, b: f64, s: f64| {
+                let r = 1.0 - s;
+                3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+            };
+            let (mut lo, mut hi) = (0.0, 1.0);
+            for _ in 0..60 {
+                let s = (lo + hi) / 2.0;
+                if bezier(x1 as f64, x2 as f64, s) < x as f64 {
+                    lo = s;
+                } else {
+                    hi = s;
+                }
+            }
+            bezier(y1 as f64, y2 as f64, lo) as f32
+        };
+
+        let curves = [
+            [0.42, 0.0, 0.58, 1.0],
+            [0.25, 0.1, 0.25, 1.0],
+            [1.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0],
+            // Flat at both ends, where Newton's method stalls
+            [0.0, 0.0, 1.0, 1.0],
+            [0.9, 0.0, 1.0, 0.1],
+            // Overshooting
+            [0.5, -0.5, 0.5, 1.5],
+        ];
+        for curve in curves {
+            for i in 0..=20 {
+                let x = i as f32 / 20.0;
+                let (y, expected) = (cubic_bezier(curve, x), reference(curve, x));
+                assert!(approx(y, expected), "{curve:?} at {x}: {y} != {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn parses_clips() {
+        let clip = Clip::from_json(
+            r#"{
+                "name": "nod",
+                "tracks": [{
+                    "param": "Head",
+                    "axis": "y",
+                    "keyframes": [
+                        { "time": 2, "value": 0 },
+                        { "time": 0, "value": 1, "interpolation": "bezier", "ease": [0, 0, 1, 1] },
+                        { "time": 1, "value": -1, "interpolation": "step" }
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(clip.name, "nod");
+        assert_eq!(clip.duration, 2.0);
+        let track = &clip.tracks[0];
+        assert_eq!(track.axis, ParamAxis::Y);
+        let times: Vec<_> = track.keyframes.iter().map(|k| k.time).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0]);
+        assert!(matches!(
+            track.keyframes[1].interpolation,
+            Interpolation::Step
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_clips() {
+        let invalid = [
+            r#"{"tracks": [{"keyframes": []}]}"#,
+            r#"{"tracks": [{"param": "p", "axis": "z"}]}"#,
+            r#"{"tracks": [{"param": "p", "keyframes": [{"value": 1}]}]}"#,
+            r#"{"tracks": [{"param": "p", "keyframes": [{"time": 0, "value": 1, "interpolation": "cubic"}]}]}"#,
+            r#"{"tracks": [{"param": "p", "keyframes": [{"time": 0, "value": 1, "interpolation": "bezier", "ease": [0, 0, 1]}]}]}"#,
+            "not json",
+        ];
+        for src in invalid {
+            assert!(Clip::from_json(src).is_err(), "{src} parsed");
+        }
+    }
+
+    #[test]
+    fn loops_and_stops() {
+        let mut player = ClipPlayer::new(ramp("p", 0.0, 1.0));
+        player.playing = true;
+        player.update(1.25);
+        assert!(approx(player.time, 0.25));
+        player.speed = -1.0;
+        player.update(0.5);
+        assert!(approx(player.time, 0.75));
+
+        player.looping = false;
+        player.speed = 1.0;
+        player.update(0.5);
+        assert_eq!(player.time, 1.0);
+        assert!(!player.playing);
+
+        player.playing = true;
+        player.speed = -2.0;
+        player.update(0.25);
+        assert!(player.playing);
+        player.update(1.0);
+        assert_eq!(player.time, 0.0);
+        assert!(!player.playing);
+    }
+
+    #[test]
+    fn empty_frames_dont_stop_a_clip_at_its_start() {
+        let mut player = ClipPlayer::new(ramp("p", 0.0, 1.0));
+        player.looping = false;
+        player.playing = true;
+        player.update(0.0);
+        assert!(player.playing);
+        player.update(0.5);
+        assert!(approx(player.time, 0.5));
+    }
+
+    #[test]
+    fn seeking_stays_within_the_clip() {
+        let mut player = ClipPlayer::new(ramp("p", 0.0, 1.0));
+        player.seek(3.0);
+        assert_eq!(player.time, 1.0);
+        player.seek(-1.0);
+        assert_eq!(player.time, 0.0);
+    }
+
+    #[test]
+    fn crossfades_between_clips() {
+        let mut animator = Animator::default();
+        let a = animator.add(ramp("p", 0.0, 1.0));
+        let b = animator.add(ramp("p", 0.0, 1.0));
+        assert!(!animator.is_active());
+
+        animator.crossfade(b, 0.5).unwrap();
+        assert!(animator.is_active());
+        animator.update(0.25);
+        assert!(approx(animator.players[a].weight, 0.5));
+        assert!(approx(animator.players[b].weight, 1.0));
+        assert!(animator.players[b].playing);
+        animator.update(0.25);
+        assert_eq!(animator.players[a].weight, 0.0);
+
+        // An ended one-shot clip starts over
+        let player = animator.player_mut(b).unwrap();
+        player.looping = false;
+        player.time = 1.0;
+        animator.crossfade(b, 0.0).unwrap();
+        assert_eq!(animator.players[b].time, 0.0);
+
+        assert!(animator.crossfade(7, 1.0).is_err());
+    }
+
+    #[test]
+    fn blends_clips_by_weight() {
+        let mut animator = Animator::default();
+        animator.add(ramp("p", 10.0, 10.0));
+        animator.add(ramp("p", 20.0, 20.0));
+        animator.add(ramp("q", 4.0, 4.0));
+        animator.add(ramp("missing", 1.0, 1.0));
+        let base = |param: &str, _| match param {
+            "p" | "q" => Some(0.0),
+            _ => None,
+        };
+
+        // Weights adding up to more than 1 are normalized
+        let blended = animator.blend(base);
+        assert!(approx(blended[&("p", ParamAxis::X)], 15.0));
+        assert!(approx(blended[&("q", ParamAxis::X)], 4.0));
+        assert!(!blended.contains_key(&("missing", ParamAxis::X)));
+
+        // Below 1, the base value shows through
+        animator.players[0].set_weight(0.25);
+        animator.players[1].set_weight(0.0);
+        animator.players[2].set_weight(0.5);
+        let blended = animator.blend(base);
+        assert!(approx(blended[&("p", ParamAxis::X)], 2.5));
+        assert!(approx(blended[&("q", ParamAxis::X)], 2.0));
+    }
+}
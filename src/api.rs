@@ -0,0 +1,549 @@
+//! JavaScript bindings to embed the viewer in another web app.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures_channel::oneshot;
+use glam::vec2;
+use inox2d::formats::inp::parse_inp;
+use js_sys::{Array, Function, Object, Promise, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+use web_sys::HtmlCanvasElement;
+use winit::event_loop::EventLoop;
+use winit::platform::web::WindowBuilderExtWebSys;
+use winit::window::WindowBuilder;
+
+use crate::animation::{Clip, ClipPlayer};
+use crate::background::Background;
+use crate::canvas_fit;
+use crate::capture::CaptureOptions;
+use crate::dropzone;
+use crate::export;
+use crate::idle::{IdleAnimator, IdleConfig};
+use crate::loader::PuppetSource;
+use crate::perf::{PerfStats, HISTOGRAM_BOUNDS};
+use crate::pointer_tracking::PointerTracker;
+use crate::puppet_scene::PuppetTransform;
+use crate::recorder::{RecordOptions, VideoFormat};
+use crate::tracking::{Protocol, TrackingMapping};
+use crate::viewer::{self, Viewer};
+use crate::web;
+
+/// A puppet viewer rendering into an existing canvas.
+///
+/// winit only supports a single event loop, so only one viewer can be attached per page.
+#[wasm_bindgen]
+pub struct PuppetViewer {
+    viewer: Rc<RefCell<Viewer>>,
+}
+
+#[wasm_bindgen]
+impl PuppetViewer {
+    fn request_capture(
+        &self,
+        scale: Option<f32>,
+        transparent: Option<bool>,
+    ) -> oneshot::Receiver<anyhow::Result<Vec<u8>>> {
+        let defaults = CaptureOptions::default();
+        let options = CaptureOptions {
+            scale: scale.unwrap_or(defaults.scale),
+            transparent: transparent.unwrap_or(defaults.transparent),
+        };
+
+        let (tx, rx) = oneshot::channel();
+        self.viewer.borrow_mut().capture(options, move |png| {
+            let _ = tx.send(png);
+        });
+        rx
+    }
+
+    /// Initializes wgpu on the given canvas and starts the render loop.
+    ///
+    /// Rejects when WebGPU is unavailable, for the page to attach with the WebGL2 build
+    /// instead, from `webgl/inochi2d-wasm.js` in trunk's output.
+    pub async fn attach(canvas: HtmlCanvasElement) -> Result<PuppetViewer, JsError> {
+        if !cfg!(feature = "webgl") && !web::webgpu_works().await {
+            return Err(JsError::new(
+                "WebGPU is unavailable in this browser, use the WebGL2 build instead",
+            ));
+        }
+
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_canvas(Some(canvas))
+            .build(&event_loop)
+            .map_err(to_js)?;
+
+        let viewer = Viewer::new(window).await.map_err(to_js)?;
+        let viewer = Rc::new(RefCell::new(viewer));
+        viewer::spawn_event_loop(event_loop, viewer.clone());
+
+        Ok(Self { viewer })
+    }
+
+    /// Loads an `.inp` puppet, replacing every puppet in the scene.
+    #[wasm_bindgen(js_name = loadPuppet)]
+    pub fn load_puppet(&self, bytes: &[u8]) -> Result<(), JsError> {
+        let model = parse_inp(bytes)?;
+        let mut viewer = self.viewer.borrow_mut();
+        // Supersedes any pending URL load
+        let ticket = viewer.begin_load();
+        viewer.finish_load(ticket, model).map_err(to_js)
+    }
+
+    /// Adds an `.inp` puppet on top of the scene and selects it, returning its id.
+    #[wasm_bindgen(js_name = addPuppet)]
+    pub fn add_puppet(&self, bytes: &[u8]) -> Result<u32, JsError> {
+        let model = parse_inp(bytes)?;
+        self.viewer.borrow_mut().add_puppet(model).map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = removePuppet)]
+    pub fn remove_puppet(&self, id: u32) -> bool {
+        self.viewer.borrow_mut().remove_puppet(id)
+    }
+
+    /// Selects the puppet that parameters are set on, and that the mouse drags.
+    #[wasm_bindgen(js_name = selectPuppet)]
+    pub fn select_puppet(&self, id: Option<u32>) {
+        self.viewer.borrow_mut().select(id);
+    }
+
+    /// Places a puppet in the scene. Puppets with a higher `z` are drawn on top.
+    #[wasm_bindgen(js_name = setTransform)]
+    pub fn set_transform(
+        &self,
+        id: u32,
+        x: f32,
+        y: f32,
+        scale: f32,
+        rotation: f32,
+        z: i32,
+    ) -> Result<(), JsError> {
+        let transform = PuppetTransform {
+            position: vec2(x, y),
+            scale,
+            rotation,
+            z,
+        };
+        self.viewer
+            .borrow_mut()
+            .set_transform(id, transform)
+            .map_err(to_js)
+    }
+
+    /// Removes every puppet and frees its textures, keeping the GPU device alive.
+    #[wasm_bindgen(js_name = unloadPuppet)]
+    pub fn unload_puppet(&self) {
+        self.viewer.borrow_mut().unload_puppet();
+    }
+
+    /// Lets the user drop puppet files onto the canvas, or pick one by double-clicking it.
+    #[wasm_bindgen(js_name = enableFileDrop)]
+    pub fn enable_file_drop(&self) -> Result<(), JsError> {
+        let canvas = self.viewer.borrow().canvas();
+        dropzone::install(&canvas, self.viewer.clone()).map_err(to_js)
+    }
+
+    /// Shows sliders and pads for every parameter of the selected puppet below the canvas.
+    #[wasm_bindgen(js_name = showParamPanel)]
+    pub fn show_param_panel(&self) -> Result<(), JsError> {
+        self.viewer.borrow_mut().show_param_panel().map_err(to_js)
+    }
+
+    /// Makes the selected puppet follow the cursor.
+    ///
+    /// `mapping` is an optional JSON table binding cursor axes to parameters,
+    /// defaulting to the parameter names of the Inochi2D example puppets.
+    #[wasm_bindgen(js_name = enablePointerTracking)]
+    pub fn enable_pointer_tracking(&self, mapping: Option<String>) -> Result<(), JsError> {
+        let tracker = match mapping {
+            Some(mapping) => PointerTracker::from_json(&mapping).map_err(to_js)?,
+            None => PointerTracker::default(),
+        };
+        self.viewer.borrow_mut().set_pointer_tracking(Some(tracker));
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = disablePointerTracking)]
+    pub fn disable_pointer_tracking(&self) {
+        self.viewer.borrow_mut().set_pointer_tracking(None);
+    }
+
+    /// Makes the selected puppet blink, breathe and sway on its own.
+    ///
+    /// `config` is an optional JSON object naming the parameters to animate,
+    /// defaulting to the parameter names of the Inochi2D example puppets.
+    #[wasm_bindgen(js_name = enableIdleAnimation)]
+    pub fn enable_idle_animation(&self, config: Option<String>) -> Result<(), JsError> {
+        let idle = match config {
+            Some(config) => IdleAnimator::new(IdleConfig::from_json(&config).map_err(to_js)?),
+            None => IdleAnimator::default(),
+        };
+        self.viewer.borrow_mut().set_idle_animation(Some(idle));
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = disableIdleAnimation)]
+    pub fn disable_idle_animation(&self) {
+        self.viewer.borrow_mut().set_idle_animation(None);
+    }
+
+    /// Loads a keyframe animation from JSON, returning its index. It starts paused.
+    #[wasm_bindgen(js_name = loadAnimation)]
+    pub fn load_animation(&self, src: &str) -> Result<usize, JsError> {
+        let clip = Clip::from_json(src).map_err(to_js)?;
+        Ok(self.viewer.borrow_mut().animator.add(clip))
+    }
+
+    #[wasm_bindgen(js_name = playAnimation)]
+    pub fn play_animation(&self, index: usize) -> Result<(), JsError> {
+        self.with_player(index, |player| player.playing = true)
+    }
+
+    #[wasm_bindgen(js_name = pauseAnimation)]
+    pub fn pause_animation(&self, index: usize) -> Result<(), JsError> {
+        self.with_player(index, |player| player.playing = false)
+    }
+
+    /// Jumps to a time in the animation, in seconds.
+    #[wasm_bindgen(js_name = seekAnimation)]
+    pub fn seek_animation(&self, index: usize, time: f32) -> Result<(), JsError> {
+        self.with_player(index, |player| player.seek(time))
+    }
+
+    /// Sets the playback rate, negative values playing backwards.
+    #[wasm_bindgen(js_name = setAnimationSpeed)]
+    pub fn set_animation_speed(&self, index: usize, speed: f32) -> Result<(), JsError> {
+        self.with_player(index, |player| player.speed = speed)
+    }
+
+    #[wasm_bindgen(js_name = setAnimationLoop)]
+    pub fn set_animation_loop(&self, index: usize, looping: bool) -> Result<(), JsError> {
+        self.with_player(index, |player| player.looping = looping)
+    }
+
+    /// Sets how much the animation influences the puppet, from 0 to 1.
+    #[wasm_bindgen(js_name = setAnimationWeight)]
+    pub fn set_animation_weight(&self, index: usize, weight: f32) -> Result<(), JsError> {
+        self.with_player(index, |player| player.set_weight(weight))
+    }
+
+    /// Plays an animation, fading it in and every other animation out over `duration` seconds.
+    #[wasm_bindgen(js_name = crossfadeAnimation)]
+    pub fn crossfade_animation(&self, index: usize, duration: f32) -> Result<(), JsError> {
+        let mut viewer = self.viewer.borrow_mut();
+        viewer.animator.crossfade(index, duration).map_err(to_js)?;
+        viewer.wake();
+        Ok(())
+    }
+
+    /// Drives the selected puppet from a face tracker streaming over a WebSocket.
+    ///
+    /// `protocol` is `"vmc"`, `"openseeface"` or `"vtubestudio"`, the latter two through
+    /// a relay forwarding UDP packets. `mapping` is an optional JSON table binding tracking
+    /// values to parameters, with smoothing and dead zones. Lost connections are retried.
+    #[wasm_bindgen(js_name = connectTracker)]
+    pub fn connect_tracker(
+        &self,
+        url: &str,
+        protocol: &str,
+        mapping: Option<String>,
+    ) -> Result<(), JsError> {
+        let protocol = Protocol::parse(protocol).map_err(to_js)?;
+        let mapping = match mapping {
+            Some(mapping) => TrackingMapping::from_json(&mapping).map_err(to_js)?,
+            None => TrackingMapping::default(),
+        };
+
+        self.viewer
+            .borrow_mut()
+            .connect_tracker(url, protocol, mapping)
+            .map_err(to_js)
+    }
+
+    /// Uses the tracked user's current head pose as the neutral one.
+    #[wasm_bindgen(js_name = calibrateTracker)]
+    pub fn calibrate_tracker(&self) -> Result<(), JsError> {
+        self.viewer.borrow_mut().calibrate_tracker().map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = disconnectTracker)]
+    pub fn disconnect_tracker(&self) {
+        self.viewer.borrow_mut().disconnect_tracker();
+    }
+
+    /// Where the tracker connection stands: `"connecting"`, `"open"` or `"closed"` while
+    /// waiting to reconnect, or null without a tracker.
+    #[wasm_bindgen(getter, js_name = trackerStatus)]
+    pub fn tracker_status(&self) -> Option<String> {
+        let status = self.viewer.borrow().tracker_status()?;
+        Some(status.name().to_owned())
+    }
+
+    /// Fetches and loads a puppet from a relative, absolute or `blob:` URL.
+    #[wasm_bindgen(js_name = loadPuppetFromUrl)]
+    pub fn load_puppet_from_url(&self, url: String) -> Promise {
+        let viewer = self.viewer.clone();
+        let ticket = viewer.borrow_mut().begin_load();
+        future_to_promise(async move {
+            let model = PuppetSource::from_url(&url)
+                .map_err(to_js)?
+                .load()
+                .await
+                .map_err(to_js)?;
+            viewer
+                .borrow_mut()
+                .finish_load(ticket, model)
+                .map_err(to_js)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Sets a parameter of the selected puppet, applied on every frame until reset.
+    /// For 1D parameters, `y` is ignored.
+    #[wasm_bindgen(js_name = setParam)]
+    pub fn set_param(&self, name: &str, x: f32, y: f32) -> Result<(), JsError> {
+        self.viewer
+            .borrow_mut()
+            .set_param(name, vec2(x, y))
+            .map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = resetParams)]
+    pub fn reset_params(&self) {
+        self.viewer.borrow_mut().reset_params();
+    }
+
+    /// Sets what puppets are drawn over: `"transparent"` (the default), `"chroma"`
+    /// for chroma-key green, or a CSS hex color like `"#336699"`.
+    #[wasm_bindgen(js_name = setBackground)]
+    pub fn set_background(&self, background: &str) -> Result<(), JsError> {
+        let background = Background::parse(background).map_err(to_js)?;
+        self.viewer
+            .borrow_mut()
+            .set_background(background)
+            .map_err(to_js)
+    }
+
+    /// Draws puppets over a PNG or JPEG image, stretched over the canvas.
+    #[wasm_bindgen(js_name = setBackgroundImage)]
+    pub fn set_background_image(&self, bytes: &[u8]) -> Result<(), JsError> {
+        let background = Background::image(bytes).map_err(to_js)?;
+        self.viewer
+            .borrow_mut()
+            .set_background(background)
+            .map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = setCamera)]
+    pub fn set_camera(&self, x: f32, y: f32, scale: f32) {
+        self.viewer.borrow_mut().set_camera(vec2(x, y), scale);
+    }
+
+    /// Captures the next frame, resolving to PNG bytes in a `Uint8Array`.
+    ///
+    /// `scale` multiplies the window size, and `transparent` defaults to true.
+    pub fn capture(&self, scale: Option<f32>, transparent: Option<bool>) -> Promise {
+        let rx = self.request_capture(scale, transparent);
+        future_to_promise(async move {
+            let png = rx.await.map_err(to_js)?.map_err(to_js)?;
+            Ok(js_sys::Uint8Array::from(png.as_slice()).into())
+        })
+    }
+
+    /// Captures the next frame and offers it as a PNG download.
+    #[wasm_bindgen(js_name = downloadCapture)]
+    pub fn download_capture(
+        &self,
+        scale: Option<f32>,
+        transparent: Option<bool>,
+        filename: Option<String>,
+    ) -> Promise {
+        let rx = self.request_capture(scale, transparent);
+        future_to_promise(async move {
+            let png = rx.await.map_err(to_js)?.map_err(to_js)?;
+            let filename = filename.as_deref().unwrap_or("puppet.png");
+            export::download(&png, "image/png", filename).map_err(to_js)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Captures the next frame and copies it to the clipboard.
+    /// Must be called while handling a user gesture, such as a click.
+    #[wasm_bindgen(js_name = copyCapture)]
+    pub fn copy_capture(
+        &self,
+        scale: Option<f32>,
+        transparent: Option<bool>,
+    ) -> Result<Promise, JsError> {
+        let rx = self.request_capture(scale, transparent);
+        export::copy_png(async move { rx.await? }).map_err(to_js)
+    }
+
+    /// Starts recording a clip, in `"gif"` (the default) or `"apng"` format.
+    ///
+    /// With `deterministic`, time advances by exactly one frame per rendered frame.
+    #[wasm_bindgen(js_name = startRecording)]
+    pub fn start_recording(
+        &self,
+        format: Option<String>,
+        fps: Option<f32>,
+        scale: Option<f32>,
+        deterministic: Option<bool>,
+    ) -> Result<(), JsError> {
+        let defaults = RecordOptions::default();
+        let format = match format {
+            Some(format) => VideoFormat::parse(&format).map_err(to_js)?,
+            None => defaults.format,
+        };
+        let options = RecordOptions {
+            format,
+            fps: fps.unwrap_or(defaults.fps),
+            scale: scale.unwrap_or(defaults.scale),
+            deterministic: deterministic.unwrap_or(defaults.deterministic),
+        };
+        self.viewer
+            .borrow_mut()
+            .start_recording(options)
+            .map_err(to_js)
+    }
+
+    /// Stops recording, resolving to the encoded clip in a `Uint8Array`.
+    #[wasm_bindgen(js_name = stopRecording)]
+    pub fn stop_recording(&self) -> Result<Promise, JsError> {
+        let (tx, rx) = oneshot::channel();
+        self.viewer
+            .borrow_mut()
+            .stop_recording(move |clip| {
+                let _ = tx.send(clip);
+            })
+            .map_err(to_js)?;
+
+        Ok(future_to_promise(async move {
+            let clip = rx.await.map_err(to_js)?.map_err(to_js)?;
+            Ok(js_sys::Uint8Array::from(clip.as_slice()).into())
+        }))
+    }
+
+    /// The graphics API in use, `"WebGPU"` or `"WebGL2"`.
+    #[wasm_bindgen(getter)]
+    pub fn backend(&self) -> String {
+        self.viewer.borrow().backend_name().to_owned()
+    }
+
+    /// How often rendering recovered from a lost surface or device, as
+    /// `{ surfaceLost, surfaceOutdated, surfaceTimeouts, deviceLost }` counts.
+    #[wasm_bindgen(getter, js_name = recoveryStats)]
+    pub fn recovery_stats(&self) -> Object {
+        let stats = self.viewer.borrow().stats;
+        let object = Object::new();
+        for (key, count) in [
+            ("surfaceLost", stats.surface_lost),
+            ("surfaceOutdated", stats.surface_outdated),
+            ("surfaceTimeouts", stats.surface_timeouts),
+            ("deviceLost", stats.device_lost),
+        ] {
+            let _ = Reflect::set(&object, &key.into(), &count.into());
+        }
+        object
+    }
+
+    /// Keeps the canvas filling its parent element, which needs a size of its own.
+    ///
+    /// Renders at the device pixel ratio, up to `maxPixelRatio` (2 by default).
+    #[wasm_bindgen(js_name = fitToParent)]
+    pub fn fit_to_parent(&self, max_pixel_ratio: Option<f64>) -> Result<(), JsError> {
+        let canvas = self.viewer.borrow().canvas();
+        let max_pixel_ratio = max_pixel_ratio.unwrap_or(canvas_fit::DEFAULT_MAX_PIXEL_RATIO);
+        canvas_fit::install(&canvas, max_pixel_ratio, self.viewer.clone()).map_err(to_js)
+    }
+
+    /// Shows frame timing stats over the canvas.
+    #[wasm_bindgen(js_name = showHud)]
+    pub fn show_hud(&self) -> Result<(), JsError> {
+        self.viewer.borrow_mut().set_hud(true).map_err(to_js)
+    }
+
+    #[wasm_bindgen(js_name = hideHud)]
+    pub fn hide_hud(&self) -> Result<(), JsError> {
+        self.viewer.borrow_mut().set_hud(false).map_err(to_js)
+    }
+
+    /// Calls `callback` every second with frame timing stats, or stops when called without one.
+    ///
+    /// Stats are `{ fps, frameTime, histogram, histogramBounds, paramsTime, renderTime, gpuTime,
+    /// drawCalls, textureBytes }`, times in milliseconds and `gpuTime` null where unsupported.
+    #[wasm_bindgen(js_name = onStats)]
+    pub fn on_stats(&self, callback: Option<Function>) {
+        let callback = callback.map(|callback| {
+            Box::new(move |stats: &PerfStats| {
+                // Called while rendering, with the viewer borrowed, so the callback waits for
+                // the render to finish in case it calls back into the viewer
+                let callback = callback.clone();
+                let stats = stats_object(stats);
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(e) = callback.call1(&JsValue::NULL, &stats) {
+                        log::error!("stats callback failed: {e:?}");
+                    }
+                });
+            }) as Box<dyn FnMut(&PerfStats)>
+        });
+        self.viewer.borrow_mut().set_stats_callback(callback);
+    }
+
+    /// Caps the frame rate, from 0.1 fps up, or lifts the cap when called without one.
+    #[wasm_bindgen(js_name = setMaxFps)]
+    pub fn set_max_fps(&self, fps: Option<f32>) -> Result<(), JsError> {
+        self.viewer.borrow_mut().set_fps_cap(fps).map_err(to_js)
+    }
+
+    /// Renders continuously, even when nothing changes.
+    pub fn start(&self) {
+        self.viewer.borrow_mut().set_running(true);
+    }
+
+    /// Goes back to only rendering when something changes, which is the default.
+    pub fn stop(&self) {
+        self.viewer.borrow_mut().set_running(false);
+    }
+}
+
+impl PuppetViewer {
+    fn with_player(&self, index: usize, f: impl FnOnce(&mut ClipPlayer)) -> Result<(), JsError> {
+        let mut viewer = self.viewer.borrow_mut();
+        let player = viewer.animator.player_mut(index).map_err(to_js)?;
+        f(player);
+        viewer.wake();
+        Ok(())
+    }
+}
+
+fn stats_object(stats: &PerfStats) -> Object {
+    let histogram: Array = stats.histogram.iter().map(|&n| JsValue::from(n)).collect();
+    let bounds: Array = HISTOGRAM_BOUNDS
+        .iter()
+        .map(|&ms| JsValue::from(ms))
+        .collect();
+
+    let object = Object::new();
+    for (key, value) in [
+        ("fps", stats.fps.into()),
+        ("frameTime", stats.frame_ms.into()),
+        ("histogram", histogram.into()),
+        ("histogramBounds", bounds.into()),
+        ("paramsTime", stats.params_ms.into()),
+        ("renderTime", stats.render_ms.into()),
+        ("gpuTime", stats.gpu_ms.map_or(JsValue::NULL, JsValue::from)),
+        ("drawCalls", stats.cost.draw_calls.into()),
+        ("textureBytes", (stats.cost.texture_bytes as f64).into()),
+    ] {
+        let _ = Reflect::set(&object, &key.into(), &value);
+    }
+    object
+}
+
+fn to_js(e: impl std::fmt::Display) -> JsError {
+    JsError::new(&e.to_string())
+}
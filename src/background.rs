@@ -0,0 +1,162 @@
+//! What puppets are drawn over: nothing, a solid color or an image.
+
+use anyhow::{anyhow, Context};
+use glam::{UVec2, Vec4};
+
+use crate::readback;
+
+/// Chroma-key green, for keying puppets out in software that can't capture transparency.
+pub const CHROMA_GREEN: Vec4 = Vec4::new(0.0, 1.0, 0.0, 1.0);
+
+#[derive(Debug, Clone, Default)]
+pub enum Background {
+    /// Only the puppets are opaque, e.g. for OBS browser sources.
+    #[default]
+    Transparent,
+    /// A color with straight alpha.
+    Color(Vec4),
+    /// An image stretched over the whole target.
+    Image {
+        size: UVec2,
+        /// Premultiplied RGBA rows.
+        pixels: Vec<u8>,
+    },
+}
+
+impl Background {
+    /// Parses `transparent`, `chroma` (or `green`), or a CSS hex color like `#336699` or `#33669980`.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "transparent" => Ok(Self::Transparent),
+            "chroma" | "green" => Ok(Self::Color(CHROMA_GREEN)),
+            _ => parse_hex(s).map(Self::Color),
+        }
+    }
+
+    /// Decodes a PNG or JPEG image.
+    pub fn image(bytes: &[u8]) -> anyhow::Result<Self> {
+        let image = image::load_from_memory(bytes)
+            .context("couldn't decode background image")?
+            .to_rgba8();
+        let size = UVec2::new(image.width(), image.height());
+
+        let mut pixels = image.into_raw();
+        readback::premultiply(&mut pixels);
+
+        Ok(Self::Image { size, pixels })
+    }
+
+    /// The color the target is cleared to, premultiplied.
+    ///
+    /// With `srgb`, the color is decoded so that an sRGB target stores it unchanged.
+    pub fn clear_color(&self, srgb: bool) -> wgpu::Color {
+        match self {
+            Self::Color(color) => {
+                let decode = |c: f32| match srgb {
+                    true if c <= 0.04045 => c / 12.92,
+                    true => ((c + 0.055) / 1.055).powf(2.4),
+                    false => c,
+                };
+                wgpu::Color {
+                    r: decode(color.x * color.w) as f64,
+                    g: decode(color.y * color.w) as f64,
+                    b: decode(color.z * color.w) as f64,
+                    a: color.w as f64,
+                }
+            }
+            Self::Transparent | Self::Image { .. } => wgpu::Color::TRANSPARENT,
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> anyhow::Result<Vec4> {
+    let hex = s
+        .strip_prefix('#')
+        .with_context(|| format!("invalid background {s:?}"))?;
+    let digits = hex
+        .chars()
+        .map(|c| c.to_digit(16).map(|d| d as f32))
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("invalid color {s:?}"))?;
+
+    let channels: Vec<f32> = match digits.len() {
+        // #rgb and #rgba
+        3 | 4 => digits.iter().map(|d| d / 15.0).collect(),
+        // #rrggbb and #rrggbbaa
+        6 | 8 => digits
+            .chunks(2)
+            .map(|d| (d[0] * 16.0 + d[1]) / 255.0)
+            .collect(),
+        _ => return Err(anyhow!("invalid color {s:?}")),
+    };
+
+    Ok(Vec4::new(
+        channels[0],
+        channels[1],
+        channels[2],
+        channels.get(3).copied().unwrap_or(1.0),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(s: &str) -> Vec4 {
+        match Background::parse(s).unwrap() {
+            Background::Color(color) => color,
+            other => panic!("{s} parsed as {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(color("#f08"), Vec4::new(1.0, 0.0, 8.0 / 15.0, 1.0));
+        assert_eq!(color("#f008"), Vec4::new(1.0, 0.0, 0.0, 8.0 / 15.0));
+        assert_eq!(color("#FF0080"), Vec4::new(1.0, 0.0, 128.0 / 255.0, 1.0));
+        assert_eq!(color("#33669980"), Vec4::new(0.2, 0.4, 0.6, 128.0 / 255.0));
+    }
+
+    #[test]
+    fn parses_keywords() {
+        assert!(matches!(
+            Background::parse("transparent").unwrap(),
+            Background::Transparent
+        ));
+        assert_eq!(color("chroma"), CHROMA_GREEN);
+        assert_eq!(color("green"), CHROMA_GREEN);
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        for s in [
+            "", "#", "336699", "#12", "#12345", "#1234567", "#33669g", "red", "#ффф",
+        ] {
+            assert!(Background::parse(s).is_err(), "{s:?} parsed");
+        }
+    }
+
+    #[test]
+    fn premultiplies_the_clear_color() {
+        let background = Background::parse("#ff336680").unwrap();
+        let alpha = 128.0 / 255.0;
+
+        let linear = background.clear_color(false);
+        assert!((linear.r - alpha).abs() < 1e-6);
+        assert!((linear.g - 0.2 * alpha).abs() < 1e-6);
+        assert!((linear.b - 0.4 * alpha).abs() < 1e-6);
+        assert!((linear.a - alpha).abs() < 1e-6);
+
+        // Decoded after premultiplying, as that's what ends up stored
+        let srgb = background.clear_color(true);
+        let decode = |c: f64| ((c + 0.055) / 1.055).powf(2.4);
+        assert!((srgb.r - decode(alpha)).abs() < 1e-6);
+        assert!((srgb.g - decode(0.2 * alpha)).abs() < 1e-6);
+        assert!((srgb.a - alpha).abs() < 1e-6);
+
+        assert_eq!(
+            Background::Transparent.clear_color(true),
+            wgpu::Color::TRANSPARENT
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Sizing the canvas to its container, at the display's pixel density.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Context;
+use wasm_bindgen::prelude::*;
+use web_sys::{AddEventListenerOptions, Element, HtmlCanvasElement, ResizeObserver, Window};
+use winit::dpi::PhysicalSize;
+
+use crate::loader::js_error;
+use crate::viewer::Viewer;
+
+/// Pixel ratio used when none is given, as rendering at 3x or more costs a lot for little gain.
+pub const DEFAULT_MAX_PIXEL_RATIO: f64 = 2.0;
+
+/// Keeps the canvas filling its parent element, rendering at the device pixel ratio up to
+/// `max_pixel_ratio`, even once the window moves to another display or gets zoomed.
+///
+/// The parent needs a size of its own, e.g. from CSS, as the canvas doesn't give it one.
+pub fn install(
+    canvas: &HtmlCanvasElement,
+    max_pixel_ratio: f64,
+    viewer: Rc<RefCell<Viewer>>,
+) -> anyhow::Result<()> {
+    let window = web_sys::window().context("no window")?;
+    let container = canvas
+        .parent_element()
+        .context("the canvas isn't in the document")?;
+
+    fill(canvas)?;
+
+    let fit_canvas = canvas.clone();
+    let ratio_window = window.clone();
+    let fit: Rc<dyn Fn()> = Rc::new(move || {
+        // winit sets a fixed CSS size whenever it resizes the canvas itself
+        if let Err(e) = fill(&fit_canvas) {
+            log::error!("couldn't size the canvas: {e}");
+            return;
+        }
+
+        let css = fit_canvas.get_bounding_client_rect();
+        let device_ratio = ratio_window.device_pixel_ratio();
+        let ratio = device_ratio.min(max_pixel_ratio);
+        let size = PhysicalSize::new(
+            (css.width() * ratio).round() as u32,
+            (css.height() * ratio).round() as u32,
+        );
+        // Hidden containers have no size, and neither can the surface
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        fit_canvas.set_width(size.width);
+        fit_canvas.set_height(size.height);
+
+        let mut viewer = viewer.borrow_mut();
+        // winit reports the cursor at the full device ratio
+        viewer.set_cursor_scale((ratio / device_ratio) as f32);
+        viewer.resize(size);
+    });
+
+    // Changing the pixel ratio alone doesn't resize anything
+    watch_pixel_ratio(&window, fit.clone())?;
+
+    let on_resize = Closure::<dyn FnMut()>::new(move || fit());
+
+    let observer = ResizeObserver::new(on_resize.as_ref().unchecked_ref()).map_err(js_error)?;
+    // The canvas is observed too, to notice winit changing its size
+    let canvas: &Element = canvas.as_ref();
+    for element in [&container, canvas] {
+        observer.observe(element);
+    }
+    on_resize.forget();
+
+    Ok(())
+}
+
+/// Calls `on_change` whenever the device pixel ratio changes.
+fn watch_pixel_ratio(window: &Window, on_change: Rc<dyn Fn()>) -> anyhow::Result<()> {
+    // The query only stops matching once, so a new one is needed for the next change
+    let query = format!("(resolution: {}dppx)", window.device_pixel_ratio());
+    let list = window
+        .match_media(&query)
+        .map_err(js_error)?
+        .context("this browser can't watch the pixel ratio")?;
+
+    let next_window = window.clone();
+    let on_ratio_change = Closure::once_into_js(move || {
+        on_change();
+        if let Err(e) = watch_pixel_ratio(&next_window, on_change) {
+            log::error!("couldn't keep watching the pixel ratio: {e}");
+        }
+    });
+    let mut options = AddEventListenerOptions::new();
+    options.once(true);
+    list.add_event_listener_with_callback_and_add_event_listener_options(
+        "change",
+        on_ratio_change.unchecked_ref(),
+        &options,
+    )
+    .map_err(js_error)
+}
+
+fn fill(canvas: &HtmlCanvasElement) -> anyhow::Result<()> {
+    let style = canvas.style();
+    for (property, value) in [("display", "block"), ("width", "100%"), ("height", "100%")] {
+        style.set_property(property, value).map_err(js_error)?;
+    }
+    Ok(())
+}
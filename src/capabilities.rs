@@ -0,0 +1,99 @@
+//! Negotiating device features and limits with what the adapter supports.
+
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use inox2d::model::Model;
+use log::{debug, info, warn};
+
+/// Features the renderer uses when available.
+const WANTED_FEATURES: wgpu::Features =
+    wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER.union(wgpu::Features::TIMESTAMP_QUERY);
+
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+}
+
+impl Capabilities {
+    /// Requests the wanted features and the usual limits, but only as far as the adapter goes.
+    pub fn negotiate(adapter: &wgpu::Adapter) -> Self {
+        let info = adapter.get_info();
+        let supported = adapter.limits();
+
+        let features = adapter.features() & WANTED_FEATURES;
+        let missing = WANTED_FEATURES - features;
+        if !missing.is_empty() {
+            warn!("{} adapter lacks {missing:?}, doing without", info.name);
+        }
+
+        // Start from the lowest tier this backend could be, then take every texture size the
+        // adapter allows, since puppets can be large
+        let base = match info.backend {
+            wgpu::Backend::Gl => wgpu::Limits::downlevel_webgl2_defaults(),
+            _ if wgpu::Limits::default().check_limits(&supported) => wgpu::Limits::default(),
+            _ => wgpu::Limits::downlevel_defaults(),
+        };
+        let limits = base.using_resolution(supported);
+        info!(
+            "device limits: {} px textures, {} bind groups",
+            limits.max_texture_dimension_2d, limits.max_bind_groups
+        );
+
+        Self { features, limits }
+    }
+
+    pub fn device_descriptor(&self) -> wgpu::DeviceDescriptor<'static> {
+        wgpu::DeviceDescriptor {
+            label: None,
+            features: self.features,
+            limits: self.limits.clone(),
+        }
+    }
+
+    /// Whether samplers can clamp to a border color.
+    ///
+    /// Without it, parts sampled past the edge of their texture smear its outermost pixels
+    /// instead of fading out. Emulating it takes padding textures on upload and remapping the
+    /// UVs of every mesh, both inside the inox2d fork, so it's left to a change there.
+    pub fn clamp_to_border(&self) -> bool {
+        self.features
+            .contains(wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER)
+    }
+
+    /// Whether GPU time can be measured with timestamp queries.
+    pub fn gpu_timing(&self) -> bool {
+        self.features.contains(wgpu::Features::TIMESTAMP_QUERY)
+    }
+
+    pub fn max_texture_size(&self) -> u32 {
+        self.limits.max_texture_dimension_2d
+    }
+
+    /// Checks that every texture of a puppet fits on the device.
+    pub fn check_model(&self, model: &Model) -> anyhow::Result<()> {
+        let max = self.max_texture_size();
+        for (i, texture) in model.textures.iter().enumerate() {
+            match texture_size(&texture.data) {
+                Some((width, height)) if width > max || height > max => {
+                    return Err(anyhow!(
+                        "puppet texture {i} is {width}x{height}, but this device only \
+                         supports textures up to {max}x{max}"
+                    ));
+                }
+                Some(_) => {}
+                None => debug!("couldn't read the size of puppet texture {i}"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The size of an encoded texture, read from its header.
+pub fn texture_size(data: &[u8]) -> Option<(u32, u32)> {
+    image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+}
@@ -0,0 +1,141 @@
+//! Capturing rendered frames as PNG images, at any resolution.
+
+use anyhow::anyhow;
+use log::info;
+
+use crate::readback::{self, MapResult, Readback};
+
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureOptions {
+    /// Output size relative to the window, e.g. 2 for twice the width and height.
+    pub scale: f32,
+    /// Whether to leave the scene's background out, or draw it and flatten what's left over white.
+    pub transparent: bool,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            transparent: true,
+        }
+    }
+}
+
+type Callback = Box<dyn FnOnce(anyhow::Result<Vec<u8>>)>;
+
+struct PendingCapture {
+    readback: Readback,
+    mapped: MapResult,
+    transparent: bool,
+    callback: Callback,
+}
+
+/// Captures waiting for the next frame, then for their readback.
+#[derive(Default)]
+pub struct Captures {
+    requests: Vec<(CaptureOptions, Callback)>,
+    pending: Vec<PendingCapture>,
+}
+
+impl Captures {
+    /// Queues a capture of the next frame, `callback` receiving the encoded PNG.
+    pub fn request(
+        &mut self,
+        options: CaptureOptions,
+        callback: impl FnOnce(anyhow::Result<Vec<u8>>) + 'static,
+    ) {
+        self.requests.push((options, Box::new(callback)));
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !self.requests.is_empty() || !self.pending.is_empty()
+    }
+
+    /// Takes the captures to render this frame.
+    pub fn take_requests(&mut self) -> Vec<(CaptureOptions, Callback)> {
+        std::mem::take(&mut self.requests)
+    }
+
+    /// Starts reading back a rendered capture.
+    pub fn start(
+        &mut self,
+        readback: anyhow::Result<Readback>,
+        options: CaptureOptions,
+        callback: Callback,
+    ) {
+        let readback = match readback {
+            Ok(readback) => readback,
+            Err(e) => return callback(Err(e)),
+        };
+
+        let mapped = readback.map_async();
+
+        self.pending.push(PendingCapture {
+            readback,
+            mapped,
+            transparent: options.transparent,
+            callback,
+        });
+    }
+
+    /// Fails captures being read back, e.g. because their device was lost.
+    ///
+    /// Requested captures are kept, they will be rendered on the next frame.
+    pub fn cancel(&mut self) {
+        for capture in self.pending.drain(..) {
+            (capture.callback)(Err(anyhow!("the GPU device was lost during the capture")));
+        }
+    }
+
+    /// Encodes the captures whose readback landed, and hands them to their callbacks.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if self.pending.is_empty() {
+            return;
+        }
+        readback::poll_maps(device);
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            let Some(mapped) = self.pending[i].mapped.take() else {
+                i += 1;
+                continue;
+            };
+
+            let capture = self.pending.swap_remove(i);
+            let png = mapped.map_err(anyhow::Error::from).and_then(|()| {
+                let (width, height) = (capture.readback.width(), capture.readback.height());
+                let mut rgba = capture.readback.into_rgba();
+                if capture.transparent {
+                    readback::unpremultiply(&mut rgba);
+                } else {
+                    readback::flatten(&mut rgba, [255, 255, 255]);
+                }
+                info!("captured a {width}x{height} frame");
+                readback::encode_png(width, height, &rgba)
+            });
+            (capture.callback)(png);
+        }
+    }
+}
+
+/// Saves a file for the user: as a download on the web, or in the working directory.
+pub fn save(bytes: &[u8], extension: &str, mime: &str) -> anyhow::Result<()> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        crate::export::download(bytes, mime, &format!("puppet.{extension}"))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // Files only go by their extension
+        let _ = mime;
+        let secs = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = format!("puppet-{secs}.{extension}");
+        std::fs::write(&path, bytes)?;
+        info!("saved {path}");
+        Ok(())
+    }
+}
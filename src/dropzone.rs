@@ -0,0 +1,97 @@
+//! Loading puppets dropped onto the canvas or picked with a file dialog.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::Context;
+use js_sys::Uint8Array;
+use log::info;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{DragEvent, Event, File, HtmlCanvasElement, HtmlInputElement, MouseEvent};
+
+use crate::loader::{js_error, PuppetSource};
+use crate::viewer::Viewer;
+
+/// Makes the canvas accept dropped `.inp`/`.inx` files,
+/// and opens a file picker when it is double-clicked.
+pub fn install(canvas: &HtmlCanvasElement, viewer: Rc<RefCell<Viewer>>) -> anyhow::Result<()> {
+    let document = web_sys::window()
+        .and_then(|win| win.document())
+        .context("no document")?;
+
+    // Dropping is only allowed if dragover is cancelled
+    let on_dragover = Closure::<dyn FnMut(_)>::new(|event: DragEvent| event.prevent_default());
+    canvas
+        .add_event_listener_with_callback("dragover", on_dragover.as_ref().unchecked_ref())
+        .map_err(js_error)?;
+    on_dragover.forget();
+
+    let drop_viewer = viewer.clone();
+    let on_drop = Closure::<dyn FnMut(_)>::new(move |event: DragEvent| {
+        event.prevent_default();
+
+        let file = event
+            .data_transfer()
+            .and_then(|data| data.files())
+            .and_then(|files| files.get(0));
+        if let Some(file) = file {
+            wasm_bindgen_futures::spawn_local(load_file(file, drop_viewer.clone()));
+        }
+    });
+    canvas
+        .add_event_listener_with_callback("drop", on_drop.as_ref().unchecked_ref())
+        .map_err(js_error)?;
+    on_drop.forget();
+
+    let input: HtmlInputElement = document
+        .create_element("input")
+        .map_err(js_error)?
+        .unchecked_into();
+    input.set_type("file");
+    input.set_accept(".inp,.inx");
+    input.set_hidden(true);
+    canvas.after_with_node_1(&input).map_err(js_error)?;
+
+    let picked_input = input.clone();
+    let on_change = Closure::<dyn FnMut(_)>::new(move |_: Event| {
+        if let Some(file) = picked_input.files().and_then(|files| files.get(0)) {
+            wasm_bindgen_futures::spawn_local(load_file(file, viewer.clone()));
+        }
+        // Allow picking the same file again
+        picked_input.set_value("");
+    });
+    input
+        .add_event_listener_with_callback("change", on_change.as_ref().unchecked_ref())
+        .map_err(js_error)?;
+    on_change.forget();
+
+    let on_dblclick = Closure::<dyn FnMut(_)>::new(move |_: MouseEvent| input.click());
+    canvas
+        .add_event_listener_with_callback("dblclick", on_dblclick.as_ref().unchecked_ref())
+        .map_err(js_error)?;
+    on_dblclick.forget();
+
+    Ok(())
+}
+
+async fn load_file(file: File, viewer: Rc<RefCell<Viewer>>) {
+    info!("loading puppet from {}", file.name());
+    let ticket = viewer.borrow_mut().begin_load();
+
+    let model = async {
+        let buf = JsFuture::from(file.array_buffer())
+            .await
+            .map_err(js_error)?;
+        PuppetSource::Bytes(Uint8Array::new(&buf).to_vec())
+            .load()
+            .await
+    };
+
+    let res = model
+        .await
+        .and_then(|model| viewer.borrow_mut().finish_load(ticket, model));
+    if let Err(e) = res {
+        log::error!("couldn't load {}: {}", file.name(), e);
+    }
+}
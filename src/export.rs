@@ -0,0 +1,75 @@
+//! Handing captured images over to the browser, as downloads or through the clipboard.
+
+use std::future::Future;
+
+use anyhow::Context;
+use js_sys::{Array, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+use web_sys::{Blob, BlobPropertyBag, ClipboardItem, HtmlAnchorElement, Url};
+
+use crate::loader::js_error;
+
+pub fn blob(bytes: &[u8], mime: &str) -> anyhow::Result<Blob> {
+    let parts = Array::of1(&Uint8Array::from(bytes));
+    let mut options = BlobPropertyBag::new();
+    options.type_(mime);
+    Blob::new_with_u8_array_sequence_and_options(&parts, &options).map_err(js_error)
+}
+
+/// How long the object URL of a download stays valid, in milliseconds.
+const DOWNLOAD_URL_LIFETIME: i32 = 60_000;
+
+/// Offers a file to the user, as if following a download link.
+pub fn download(bytes: &[u8], mime: &str, filename: &str) -> anyhow::Result<()> {
+    let window = web_sys::window().context("no window")?;
+    let document = window.document().context("no document")?;
+
+    let url = Url::create_object_url_with_blob(&blob(bytes, mime)?).map_err(js_error)?;
+    let link: HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(js_error)?
+        .unchecked_into();
+    link.set_href(&url);
+    link.set_download(filename);
+    link.click();
+
+    // Some browsers only start reading the blob after the click returns
+    let revoke = Closure::once_into_js(move || {
+        let _ = Url::revoke_object_url(&url);
+    });
+    window
+        .set_timeout_with_callback_and_timeout_and_arguments_0(
+            revoke.unchecked_ref(),
+            DOWNLOAD_URL_LIFETIME,
+        )
+        .map_err(js_error)?;
+    Ok(())
+}
+
+/// Copies a PNG to the clipboard, resolving the returned promise once done.
+///
+/// Browsers only allow writing to the clipboard while handling a user gesture,
+/// so the write starts right away with the image still being rendered.
+pub fn copy_png(
+    png: impl Future<Output = anyhow::Result<Vec<u8>>> + 'static,
+) -> anyhow::Result<Promise> {
+    let window = web_sys::window().context("no window")?;
+
+    let clipboard = window
+        .navigator()
+        .clipboard()
+        .context("the clipboard isn't available")?;
+
+    let image = future_to_promise(async move {
+        let png = png.await.map_err(|e| JsError::new(&e.to_string()))?;
+        let blob = blob(&png, "image/png").map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(blob.into())
+    });
+
+    let data = Object::new();
+    Reflect::set(&data, &"image/png".into(), &image).map_err(js_error)?;
+    let item = ClipboardItem::new_with_record_from_str_to_blob_promise(&data).map_err(js_error)?;
+
+    Ok(clipboard.write(&Array::of1(&item)))
+}
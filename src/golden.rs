@@ -0,0 +1,242 @@
+//! Golden-image regression tests, rendering the puppets in `tests/fixtures` headlessly.
+//!
+//! Cases are listed in `tests/golden/cases.json`. Each one is rendered and compared
+//! to `tests/golden/<name>.png`; failing cases write the rendered image and a diff
+//! to `target/golden-diffs`. Run with `UPDATE_GOLDEN=1` to accept the current output.
+//!
+//! `Squares.inp` is a hand-made puppet of two flat squares, free to redistribute, whose
+//! parts land on whole pixels so that its renders don't depend on the GPU's rasterizer.
+//! Its `Blue:: Lower` parameter moves the blue square down by up to 32 pixels.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use glam::{uvec2, vec2, UVec2, Vec2};
+use inox2d::formats::inp::parse_inp;
+
+use crate::headless::HeadlessRenderer;
+use crate::readback;
+
+/// Mismatching pixels allowed by default, as a fraction of the image.
+const DEFAULT_TOLERANCE: f32 = 0.001;
+/// Perceptual difference above which two pixels mismatch, in [0, 1].
+const DEFAULT_THRESHOLD: f32 = 0.1;
+/// Largest value of [`color_delta`], between black and white.
+const MAX_DELTA: f32 = 35215.0;
+
+struct Case {
+    name: String,
+    puppet: PathBuf,
+    size: UVec2,
+    camera_position: Vec2,
+    camera_scale: f32,
+    params: HashMap<String, Vec2>,
+    threshold: f32,
+    tolerance: f32,
+}
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn load_cases() -> anyhow::Result<Vec<Case>> {
+    let path = root().join("tests/golden/cases.json");
+    let src = std::fs::read_to_string(&path)
+        .with_context(|| format!("couldn't read {}", path.display()))?;
+    let json = json::parse(&src)?;
+
+    json["cases"]
+        .members()
+        .map(|case| {
+            let name = case["name"].as_str().context("case without a name")?;
+            let puppet = case["puppet"]
+                .as_str()
+                .with_context(|| format!("case {name:?} without a puppet"))?;
+
+            let mut params = HashMap::new();
+            for (param, value) in case["params"].entries() {
+                let x = value[0].as_f32().or(value.as_f32());
+                let x = x.with_context(|| format!("invalid value for {param:?} in {name:?}"))?;
+                params.insert(param.to_owned(), vec2(x, value[1].as_f32().unwrap_or(0.0)));
+            }
+
+            let size = &case["size"];
+            let camera = &case["camera"];
+            Ok(Case {
+                name: name.to_owned(),
+                puppet: root().join("tests/fixtures").join(puppet),
+                size: uvec2(
+                    size[0].as_u32().unwrap_or(512),
+                    size[1].as_u32().unwrap_or(512),
+                ),
+                camera_position: vec2(
+                    camera["position"][0].as_f32().unwrap_or(0.0),
+                    camera["position"][1].as_f32().unwrap_or(0.0),
+                ),
+                camera_scale: camera["scale"].as_f32().unwrap_or(0.15),
+                params,
+                threshold: case["threshold"].as_f32().unwrap_or(DEFAULT_THRESHOLD),
+                tolerance: case["tolerance"].as_f32().unwrap_or(DEFAULT_TOLERANCE),
+            })
+        })
+        .collect()
+}
+
+async fn render(case: &Case) -> anyhow::Result<Vec<u8>> {
+    let data = std::fs::read(&case.puppet)
+        .with_context(|| format!("missing fixture {}", case.puppet.display()))?;
+    let model = parse_inp(data.as_slice())?;
+
+    let mut renderer = HeadlessRenderer::new(case.size, false).await?;
+    renderer.camera.position = case.camera_position;
+    renderer.camera.scale = Vec2::splat(case.camera_scale);
+    let id = renderer.add_puppet(model)?;
+    let puppet = renderer.scene.get_mut(id).context("puppet disappeared")?;
+    // Unknown parameters would silently render the rest pose
+    for name in case.params.keys() {
+        if !puppet.model.puppet.parameters.contains_key(name) {
+            return Err(anyhow!(
+                "{} has no parameter named {name:?}",
+                case.puppet.display()
+            ));
+        }
+    }
+    puppet.params = case.params.clone();
+
+    renderer.render().await
+}
+
+fn decode_png(path: &Path) -> anyhow::Result<(UVec2, Vec<u8>)> {
+    let file = std::fs::File::open(path)?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err(anyhow!("{} isn't 8-bit RGBA", path.display()));
+    }
+    pixels.truncate(info.buffer_size());
+
+    Ok((uvec2(info.width, info.height), pixels))
+}
+
+/// Squared distance between two premultiplied pixels in YIQ space, weighted as in pixelmatch.
+///
+/// Alpha is taken into account by blending both pixels over white.
+fn color_delta(a: &[u8], b: &[u8]) -> f32 {
+    let over_white = |p: &[u8]| {
+        let alpha = p[3] as f32;
+        [0, 1, 2].map(|i| p[i] as f32 + 255.0 - alpha)
+    };
+    let ([r1, g1, b1], [r2, g2, b2]) = (over_white(a), over_white(b));
+    let (dr, dg, db) = (r1 - r2, g1 - g2, b1 - b2);
+
+    let y = dr * 0.298_895_3 + dg * 0.586_622_47 + db * 0.114_482_23;
+    let i = dr * 0.595_977_99 - dg * 0.274_176_9 - db * 0.321_801_09;
+    let q = dr * 0.211_470_19 - dg * 0.522_617_2 + db * 0.311_146_99;
+
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+/// Compares two images, returning the number of mismatching pixels and a diff image.
+///
+/// The diff shows mismatches in red over a faded copy of the expected image.
+fn compare(expected: &[u8], actual: &[u8], threshold: f32) -> (usize, Vec<u8>) {
+    let max_delta = MAX_DELTA * threshold * threshold;
+    let mut mismatches = 0;
+    let mut diff = Vec::with_capacity(expected.len());
+
+    for (e, a) in expected.chunks_exact(4).zip(actual.chunks_exact(4)) {
+        if color_delta(e, a) > max_delta {
+            mismatches += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let gray = e[0] as f32 * 0.299 + e[1] as f32 * 0.587 + e[2] as f32 * 0.114;
+            let faded = (255.0 - 0.1 * (255.0 - gray) * e[3] as f32 / 255.0) as u8;
+            diff.extend_from_slice(&[faded, faded, faded, 255]);
+        }
+    }
+
+    (mismatches, diff)
+}
+
+/// Renders a case and checks it against its golden image.
+async fn check(case: &Case, golden: &Path, update: bool) -> anyhow::Result<()> {
+    let actual = render(case).await?;
+
+    if update {
+        let png = readback::encode_premultiplied_png(case.size.x, case.size.y, &actual)?;
+        std::fs::write(golden, png)?;
+        return Ok(());
+    }
+
+    let (size, mut expected) = decode_png(golden).with_context(|| {
+        format!(
+            "couldn't load golden image {}, create it with UPDATE_GOLDEN=1",
+            golden.display()
+        )
+    })?;
+    // Rendered pixels are premultiplied, PNGs aren't
+    readback::premultiply(&mut expected);
+    if size != case.size {
+        return Err(anyhow!("golden image is {size}, expected {}", case.size));
+    }
+
+    let (mismatches, diff) = compare(&expected, &actual, case.threshold);
+    let ratio = mismatches as f32 / (size.x * size.y) as f32;
+    if ratio <= case.tolerance {
+        return Ok(());
+    }
+
+    let out = root().join("target/golden-diffs");
+    std::fs::create_dir_all(&out)?;
+    let actual_path = out.join(format!("{}.png", case.name));
+    let diff_path = out.join(format!("{}.diff.png", case.name));
+    std::fs::write(
+        &actual_path,
+        readback::encode_premultiplied_png(size.x, size.y, &actual)?,
+    )?;
+    std::fs::write(&diff_path, readback::encode_png(size.x, size.y, &diff)?)?;
+
+    Err(anyhow!(
+        "{:.3}% of pixels differ (tolerance {:.3}%), see {} and {}",
+        ratio * 100.0,
+        case.tolerance * 100.0,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+#[test]
+fn golden_images() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let cases = load_cases().unwrap();
+    assert!(
+        !cases.is_empty(),
+        "no golden cases in tests/golden/cases.json"
+    );
+
+    // Check every case before failing, so that one run reports all regressions.
+    // A missing fixture or golden image is a failure too, not a skipped case
+    let failures: Vec<_> = cases
+        .iter()
+        .filter_map(|case| {
+            let golden = root()
+                .join("tests/golden")
+                .join(format!("{}.png", case.name));
+            let res = pollster::block_on(check(case, &golden, update));
+            res.err().map(|e| format!("{}: {e:#}", case.name))
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} golden images failed:\n{}",
+        failures.len(),
+        cases.len(),
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,114 @@
+//! Rendering puppets without a window, e.g. for thumbnails and regression images on CI.
+
+use anyhow::anyhow;
+use glam::{UVec2, Vec2};
+use inox2d::math::camera::Camera;
+use inox2d::model::Model;
+use log::info;
+
+use crate::capabilities::Capabilities;
+use crate::params;
+use crate::puppet_scene::PuppetScene;
+use crate::readback;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+pub struct HeadlessRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    capabilities: Capabilities,
+    target: wgpu::Texture,
+    pub scene: PuppetScene,
+    pub camera: Camera,
+}
+
+impl HeadlessRenderer {
+    /// Sets up an offscreen target of the given size.
+    ///
+    /// Falls back to a software adapter when there is no GPU, or always uses one with `software`.
+    pub async fn new(size: UVec2, software: bool) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let request = |force_fallback_adapter| {
+            instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+        };
+
+        let adapter = if software { None } else { request(false).await };
+        let adapter = match adapter {
+            Some(adapter) => adapter,
+            None => request(true)
+                .await
+                .ok_or(anyhow!("no wgpu adapter found, not even a software one"))?,
+        };
+
+        info!("wgpu adapter: {:?}", adapter.get_info());
+
+        let capabilities = Capabilities::negotiate(&adapter);
+        let (device, queue) = adapter
+            .request_device(&capabilities.device_descriptor(), None)
+            .await?;
+
+        let max = capabilities.max_texture_size();
+        if size.max_element() > max {
+            return Err(anyhow!("can't render at {size}, the limit is {max} pixels"));
+        }
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless target"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let scene = PuppetScene::new(&device, FORMAT, size);
+        let mut camera = Camera::default();
+        camera.scale = Vec2::splat(0.15);
+
+        Ok(Self {
+            device,
+            queue,
+            capabilities,
+            target,
+            scene,
+            camera,
+        })
+    }
+
+    pub fn add_puppet(&mut self, model: Model) -> anyhow::Result<u32> {
+        self.capabilities.check_model(&model)?;
+        Ok(self.scene.add(&self.device, &self.queue, model))
+    }
+
+    /// Renders the scene, returning tightly packed RGBA rows.
+    pub async fn render(&mut self) -> anyhow::Result<Vec<u8>> {
+        for p in self.scene.puppets_mut() {
+            p.model.puppet.begin_set_params();
+            params::apply(&mut p.model.puppet, &p.params);
+            p.model.puppet.end_set_params();
+        }
+
+        let view = self
+            .target
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.scene
+            .render(&self.device, &self.queue, &self.camera, &view, true);
+
+        readback::read_rgba(&self.device, &self.queue, &self.target).await
+    }
+
+    pub async fn render_png(&mut self) -> anyhow::Result<Vec<u8>> {
+        let rgba = self.render().await?;
+        readback::encode_premultiplied_png(self.target.width(), self.target.height(), &rgba)
+    }
+}
@@ -0,0 +1,192 @@
+//! Procedural blinking, breathing and swaying, so puppets look alive without a tracker.
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use anyhow::{anyhow, Context};
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::params::{set_axis, ParamAxis};
+
+/// Default configuration, using the parameter names of the Inochi2D example puppets.
+pub const DEFAULT_CONFIG: &str = r#"{
+    "blink": {
+        "params": ["Eye:: Left:: Blink", "Eye:: Right:: Blink"],
+        "range": [0, 1],
+        "interval": [2, 6],
+        "duration": 0.15
+    },
+    "breath": { "param": "Breath", "range": [0, 1], "period": 4 },
+    "sway": { "param": "Head:: Yaw-Pitch", "amplitude": 0.15, "speed": 0.25 }
+}"#;
+
+pub struct IdleConfig {
+    pub blink_params: Vec<String>,
+    /// Blink parameter values for open and closed eyes.
+    pub blink_range: (f32, f32),
+    /// Bounds of the random delay between two blinks, in seconds.
+    pub blink_interval: (f32, f32),
+    pub blink_duration: f32,
+
+    pub breath_param: Option<String>,
+    /// Breath parameter values for empty and full lungs.
+    pub breath_range: (f32, f32),
+    pub breath_period: f32,
+
+    /// A 2D parameter moved on both axes, usually the head.
+    pub sway_param: Option<String>,
+    pub sway_amplitude: f32,
+    pub sway_speed: f32,
+}
+
+impl IdleConfig {
+    /// Parses a configuration, see [`DEFAULT_CONFIG`] for the format.
+    /// Missing sections disable the corresponding animation.
+    pub fn from_json(src: &str) -> anyhow::Result<Self> {
+        let root = json::parse(src)?;
+        let (blink, breath, sway) = (&root["blink"], &root["breath"], &root["sway"]);
+
+        let blink_params = blink["params"]
+            .members()
+            .map(|p| {
+                p.as_str()
+                    .map(str::to_owned)
+                    .context("blink params must be strings")
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            blink_params,
+            blink_range: pair(&blink["range"]).unwrap_or((0.0, 1.0)),
+            blink_interval: pair(&blink["interval"]).unwrap_or((2.0, 6.0)),
+            blink_duration: positive(&blink["duration"], 0.15, "blink duration")?,
+            breath_param: breath["param"].as_str().map(str::to_owned),
+            breath_range: pair(&breath["range"]).unwrap_or((0.0, 1.0)),
+            breath_period: positive(&breath["period"], 4.0, "breath period")?,
+            sway_param: sway["param"].as_str().map(str::to_owned),
+            sway_amplitude: sway["amplitude"].as_f32().unwrap_or(0.15),
+            sway_speed: sway["speed"].as_f32().unwrap_or(0.25),
+        })
+    }
+}
+
+/// Reads a time in seconds, which the animations divide by.
+fn positive(value: &json::JsonValue, default: f32, name: &str) -> anyhow::Result<f32> {
+    let Some(secs) = value.as_f32() else {
+        return Ok(default);
+    };
+    if secs <= 0.0 || !secs.is_finite() {
+        return Err(anyhow!(
+            "{name} must be a positive number of seconds, not {secs}"
+        ));
+    }
+    Ok(secs)
+}
+
+fn pair(value: &json::JsonValue) -> Option<(f32, f32)> {
+    Some((value[0].as_f32()?, value[1].as_f32()?))
+}
+
+pub struct IdleAnimator {
+    pub config: IdleConfig,
+    rng: u32,
+    next_blink: f32,
+}
+
+impl IdleAnimator {
+    pub fn new(config: IdleConfig) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+
+        let mut animator = Self {
+            config,
+            // xorshift gets stuck on 0
+            rng: seed | 1,
+            next_blink: 0.0,
+        };
+        animator.next_blink = animator.blink_delay();
+        animator
+    }
+
+    /// Writes the idle animation at time `t` (in seconds) into `values`.
+    pub fn write(&mut self, t: f32, puppet: &Puppet, values: &mut HashMap<String, Vec2>) {
+        // Blinking: close then reopen the eyes, then wait for a random delay
+        let mut closed = 0.0;
+        if t >= self.next_blink {
+            let phase = (t - self.next_blink) / self.config.blink_duration;
+            if phase < 1.0 {
+                closed = 1.0 - (phase * 2.0 - 1.0).abs();
+            } else {
+                self.next_blink = t + self.blink_delay();
+            }
+        }
+        let config = &self.config;
+        let (open, shut) = config.blink_range;
+        let blink = open + closed * (shut - open);
+        for param in &config.blink_params {
+            set_axis(puppet, values, param, ParamAxis::X, blink);
+        }
+
+        // Breathing: a slow sine wave
+        if let Some(param) = &config.breath_param {
+            let lungs = 0.5 - 0.5 * (t * TAU / config.breath_period).cos();
+            let (empty, full) = config.breath_range;
+            let breath = empty + lungs * (full - empty);
+            set_axis(puppet, values, param, ParamAxis::X, breath);
+        }
+
+        // Swaying: smooth noise, decorrelated between the two axes
+        if let Some(param) = &config.sway_param {
+            let x = config.sway_amplitude * fractal_noise(t * config.sway_speed, 0x5eed);
+            let y = config.sway_amplitude * fractal_noise(t * config.sway_speed, 0xb0a7);
+            set_axis(puppet, values, param, ParamAxis::X, x);
+            set_axis(puppet, values, param, ParamAxis::Y, y);
+        }
+    }
+
+    fn blink_delay(&mut self) -> f32 {
+        let (min, max) = self.config.blink_interval;
+        min + self.random() * (max - min)
+    }
+
+    /// A uniform random number in [0, 1].
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32
+    }
+}
+
+impl Default for IdleAnimator {
+    fn default() -> Self {
+        Self::new(IdleConfig::from_json(DEFAULT_CONFIG).expect("default idle config is valid"))
+    }
+}
+
+/// Two octaves of value noise, roughly in [-1, 1].
+fn fractal_noise(x: f32, seed: u32) -> f32 {
+    (value_noise(x, seed) + 0.5 * value_noise(x * 2.0, seed ^ 0xffff)) / 1.5
+}
+
+fn value_noise(x: f32, seed: u32) -> f32 {
+    let i = x.floor();
+    let f = x - i;
+    let (a, b) = (hash(i as i32, seed), hash(i as i32 + 1, seed));
+
+    // Smoothstep between the two lattice values
+    a + (b - a) * f * f * (3.0 - 2.0 * f)
+}
+
+fn hash(i: i32, seed: u32) -> f32 {
+    let mut h = (i as u32).wrapping_mul(0x9e37_79b1) ^ seed;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
@@ -0,0 +1,103 @@
+//! Where puppets come from, and how to fetch them.
+
+use anyhow::{anyhow, Context};
+use inox2d::formats::inp::parse_inp;
+use inox2d::model::Model;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+
+/// Puppet loaded when nothing else is configured, relative to the page.
+const DEFAULT_PUPPET: &str = "/assets/puppet.inp";
+
+pub enum PuppetSource {
+    /// An absolute `http(s)` URL, fetched through reqwest.
+    Http(String),
+    /// A `blob:` or `data:` URL, which only the browser's `fetch` can resolve.
+    Browser(String),
+    /// Puppet data that was already read, e.g. a `Uint8Array` passed from JS.
+    Bytes(Vec<u8>),
+}
+
+impl PuppetSource {
+    /// Parses a puppet location, resolving relative URLs against the page.
+    pub fn from_url(url: &str) -> anyhow::Result<Self> {
+        if url.starts_with("blob:") || url.starts_with("data:") {
+            return Ok(Self::Browser(url.to_owned()));
+        }
+
+        let href = web_sys::window()
+            .context("no window")?
+            .location()
+            .href()
+            .map_err(js_error)?;
+        let url = web_sys::Url::new_with_base(url, &href).map_err(js_error)?;
+
+        Ok(Self::Http(url.href()))
+    }
+
+    /// Finds the puppet to show at startup, in order of priority:
+    /// the `?puppet=` query parameter, the `data-puppet` attribute of the host element,
+    /// then the default puppet.
+    pub fn from_page(host: &web_sys::Element) -> anyhow::Result<Self> {
+        let url = query_param("puppet")?
+            .or_else(|| host.get_attribute("data-puppet"))
+            .unwrap_or_else(|| DEFAULT_PUPPET.to_owned());
+
+        Self::from_url(&url)
+    }
+
+    pub async fn load(self) -> anyhow::Result<Model> {
+        let bytes = match self {
+            Self::Http(url) => fetch_http(&url).await?,
+            Self::Browser(url) => fetch_browser(&url).await?,
+            Self::Bytes(bytes) => bytes,
+        };
+
+        Ok(parse_inp(bytes.as_slice())?)
+    }
+}
+
+async fn fetch_http(url: &str) -> anyhow::Result<Vec<u8>> {
+    let res = reqwest::Client::new()
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(res.bytes().await?.to_vec())
+}
+
+async fn fetch_browser(url: &str) -> anyhow::Result<Vec<u8>> {
+    let window = web_sys::window().context("no window")?;
+    let res: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+
+    if !res.ok() {
+        return Err(anyhow!("couldn't fetch {url}: HTTP {}", res.status()));
+    }
+
+    let buf = JsFuture::from(res.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+
+    Ok(js_sys::Uint8Array::new(&buf).to_vec())
+}
+
+/// Reads a parameter from the page's query string.
+pub fn query_param(name: &str) -> anyhow::Result<Option<String>> {
+    let search = web_sys::window()
+        .context("no window")?
+        .location()
+        .search()
+        .map_err(js_error)?;
+    let query = web_sys::UrlSearchParams::new_with_str(&search).map_err(js_error)?;
+
+    Ok(query.get(name))
+}
+
+pub fn js_error(e: JsValue) -> anyhow::Error {
+    anyhow!("{:?}", e)
+}
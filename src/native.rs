@@ -0,0 +1,218 @@
+//! Command line entry point for native builds.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use glam::{uvec2, vec2, UVec2, Vec2};
+use inox2d::formats::inp::parse_inp;
+use inox2d::model::Model;
+use log::info;
+use winit::event_loop::EventLoop;
+use winit::window::WindowBuilder;
+
+use crate::animation::{Animator, Clip};
+use crate::headless::HeadlessRenderer;
+use crate::viewer::{self, Viewer};
+
+const USAGE: &str = "\
+usage: inochi2d-wasm <puppet.inp>
+       inochi2d-wasm headless <puppet.inp> <output.png> [options]
+
+Opens the puppet in a window, or renders it to PNG images with `headless`.
+
+options:
+    --size <width>x<height>     output size, defaults to 1280x720
+    --scale <scale>             camera zoom, defaults to 0.15
+    --param <name>=<x>[,<y>]    sets a parameter, can be repeated
+    --animation <clip.json>     plays a keyframe animation
+    --frames <count>            renders numbered frames, e.g. output_0000.png
+    --fps <fps>                 animation frame rate, defaults to 30
+    --software                  renders on the CPU even if a GPU is available";
+
+pub fn main() {
+    log::set_logger(&StderrLogger).expect("no other logger is set");
+    log::set_max_level(log::LevelFilter::Info);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let res = match args.first().map(String::as_str) {
+        Some("headless") => {
+            HeadlessArgs::parse(&args[1..]).and_then(|args| pollster::block_on(run_headless(args)))
+        }
+        Some(path) if args.len() == 1 && !path.starts_with('-') => run_viewer(Path::new(path)),
+        _ => Err(anyhow!("{USAGE}")),
+    };
+
+    if let Err(e) = res {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn load_model(path: &Path) -> anyhow::Result<Model> {
+    let data = std::fs::read(path).with_context(|| format!("couldn't read {}", path.display()))?;
+    Ok(parse_inp(data.as_slice())?)
+}
+
+fn run_viewer(path: &Path) -> anyhow::Result<()> {
+    let model = load_model(path)?;
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title(format!("{} - Inochi2D", path.display()))
+        .with_inner_size(winit::dpi::PhysicalSize::<u32>::new(1280, 720))
+        .build(&event_loop)?;
+
+    let mut viewer = pollster::block_on(Viewer::new(window))?;
+    viewer.load_puppet(model)?;
+    viewer::run_event_loop(event_loop, viewer)
+}
+
+struct HeadlessArgs {
+    puppet: PathBuf,
+    output: PathBuf,
+    size: UVec2,
+    scale: f32,
+    params: HashMap<String, Vec2>,
+    animation: Option<PathBuf>,
+    frames: u32,
+    fps: f32,
+    software: bool,
+}
+
+impl HeadlessArgs {
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut positional = Vec::new();
+        let mut parsed = Self {
+            puppet: PathBuf::new(),
+            output: PathBuf::new(),
+            size: uvec2(1280, 720),
+            scale: 0.15,
+            params: HashMap::new(),
+            animation: None,
+            frames: 1,
+            fps: 30.0,
+            software: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--size" => {
+                    let size = value()?;
+                    let (w, h) = size
+                        .split_once('x')
+                        .with_context(|| format!("invalid size {size:?}"))?;
+                    parsed.size = uvec2(w.parse()?, h.parse()?);
+                    if parsed.size.min_element() == 0 {
+                        return Err(anyhow!("invalid size {size:?}, it can't be empty\n{USAGE}"));
+                    }
+                }
+                "--scale" => {
+                    let scale = value()?;
+                    parsed.scale = scale.parse()?;
+                    // Also rules NaN out
+                    if !(parsed.scale > 0.0 && parsed.scale.is_finite()) {
+                        return Err(anyhow!(
+                            "invalid scale {scale:?}, it must be positive\n{USAGE}"
+                        ));
+                    }
+                }
+                "--param" => {
+                    let param = value()?;
+                    let (name, value) = param
+                        .rsplit_once('=')
+                        .with_context(|| format!("invalid parameter {param:?}"))?;
+                    let value = match value.split_once(',') {
+                        Some((x, y)) => vec2(x.parse()?, y.parse()?),
+                        None => vec2(value.parse()?, 0.0),
+                    };
+                    parsed.params.insert(name.to_owned(), value);
+                }
+                "--animation" => parsed.animation = Some(value()?.into()),
+                "--frames" => parsed.frames = value()?.parse()?,
+                "--fps" => {
+                    let fps = value()?;
+                    parsed.fps = fps.parse()?;
+                    // Also rules NaN out
+                    if !(parsed.fps > 0.0 && parsed.fps.is_finite()) {
+                        return Err(anyhow!(
+                            "invalid frame rate {fps:?}, it must be positive\n{USAGE}"
+                        ));
+                    }
+                }
+                "--software" => parsed.software = true,
+                _ if arg.starts_with("--") => return Err(anyhow!("unknown option {arg}\n{USAGE}")),
+                _ => positional.push(PathBuf::from(arg)),
+            }
+        }
+
+        let [puppet, output]: [PathBuf; 2] = positional
+            .try_into()
+            .map_err(|_| anyhow!("expected a puppet and an output path\n{USAGE}"))?;
+        parsed.puppet = puppet;
+        parsed.output = output;
+
+        Ok(parsed)
+    }
+}
+
+async fn run_headless(args: HeadlessArgs) -> anyhow::Result<()> {
+    let model = load_model(&args.puppet)?;
+
+    let mut renderer = HeadlessRenderer::new(args.size, args.software).await?;
+    renderer.camera.scale = Vec2::splat(args.scale);
+    let id = renderer.add_puppet(model)?;
+
+    let mut animator = Animator::default();
+    if let Some(path) = &args.animation {
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        let index = animator.add(Clip::from_json(&src)?);
+        animator.player_mut(index)?.playing = true;
+    }
+
+    for frame in 0..args.frames {
+        let p = renderer.scene.get_mut(id).context("puppet disappeared")?;
+        p.params = args.params.clone();
+        animator.write(&p.model.puppet, &mut p.params);
+
+        let png = renderer.render_png().await?;
+        let path = frame_path(&args.output, frame, args.frames);
+        std::fs::write(&path, png).with_context(|| format!("couldn't write {}", path.display()))?;
+        info!("wrote {}", path.display());
+
+        // Step by exactly one frame, independently of how long rendering took
+        animator.update(1.0 / args.fps);
+    }
+
+    Ok(())
+}
+
+/// Numbers the output file when rendering several frames.
+fn frame_path(output: &Path, frame: u32, frames: u32) -> PathBuf {
+    if frames <= 1 {
+        return output.to_owned();
+    }
+
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = output.extension().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{stem}_{frame:04}.{ext}"))
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,250 @@
+//! A DOM panel with a slider or pad for every parameter of the selected puppet.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use anyhow::Context;
+use glam::{vec2, Vec2};
+use inox2d::puppet::Puppet;
+use wasm_bindgen::prelude::*;
+use web_sys::{Document, Element, HtmlInputElement, MouseEvent};
+use winit::event_loop::EventLoopProxy;
+
+use crate::loader::js_error;
+
+/// Side of the square pads used for 2D parameters, in CSS pixels.
+const PAD_SIZE: f64 = 100.0;
+
+pub struct ParamPanel {
+    document: Document,
+    root: Element,
+    /// Values the user changed since the last sync, keyed by parameter name.
+    values: Rc<RefCell<HashMap<String, Vec2>>>,
+    /// The puppet the controls were built for.
+    shown: Option<u32>,
+    /// Wakes the event loop up to render changed values.
+    wake: Option<EventLoopProxy<()>>,
+}
+
+impl ParamPanel {
+    /// Creates an empty panel right after the given element.
+    pub fn new(anchor: &Element, wake: Option<EventLoopProxy<()>>) -> anyhow::Result<Self> {
+        let document = web_sys::window()
+            .and_then(|win| win.document())
+            .context("no document")?;
+
+        let root = document.create_element("div").map_err(js_error)?;
+        root.set_class_name("inox2d-params");
+        anchor.after_with_node_1(&root).map_err(js_error)?;
+
+        Ok(Self {
+            document,
+            root,
+            values: Rc::new(RefCell::new(HashMap::new())),
+            shown: None,
+            wake,
+        })
+    }
+
+    /// Rebuilds the controls if the selected puppet changed,
+    /// then writes the values changed through the panel since the last sync into `params`.
+    ///
+    /// Values only get written once, so that they don't override the ones set from elsewhere.
+    pub fn sync(
+        &mut self,
+        id: Option<u32>,
+        puppet: Option<&Puppet>,
+        params: &mut HashMap<String, Vec2>,
+    ) {
+        if self.shown != id {
+            self.shown = id;
+            self.values.borrow_mut().clear();
+            if let Err(e) = self.rebuild(puppet, params) {
+                log::error!("couldn't build parameter panel: {e}");
+            }
+        }
+
+        params.extend(self.values.borrow_mut().drain());
+    }
+
+    fn rebuild(
+        &self,
+        puppet: Option<&Puppet>,
+        params: &HashMap<String, Vec2>,
+    ) -> anyhow::Result<()> {
+        self.root.set_inner_html("");
+        let Some(puppet) = puppet else {
+            return Ok(());
+        };
+
+        let mut names: Vec<_> = puppet.parameters.keys().collect();
+        names.sort();
+
+        for name in names {
+            let param = &puppet.parameters[name];
+            let value = params.get(name).copied().unwrap_or(param.defaults);
+
+            let row = self.document.create_element("div").map_err(js_error)?;
+            row.set_class_name("inox2d-param");
+
+            let label = self.document.create_element("label").map_err(js_error)?;
+            let readout = self.document.create_element("output").map_err(js_error)?;
+            row.append_child(&label).map_err(js_error)?;
+
+            if param.is_vec2 {
+                label.set_text_content(Some(&format!(
+                    "{name} x: [{}, {}] y: [{}, {}]",
+                    param.min.x, param.max.x, param.min.y, param.max.y
+                )));
+                let pad = self.pad(name, param.min, param.max, value, &readout)?;
+                row.append_child(&pad).map_err(js_error)?;
+            } else {
+                label.set_text_content(Some(&format!("{name} [{}, {}]", param.min.x, param.max.x)));
+                let slider = self.slider(name, param.min.x, param.max.x, value.x, &readout)?;
+                row.append_child(&slider).map_err(js_error)?;
+            }
+
+            readout.set_text_content(Some(&format_value(value, param.is_vec2)));
+            row.append_child(&readout).map_err(js_error)?;
+            self.root.append_child(&row).map_err(js_error)?;
+        }
+
+        Ok(())
+    }
+
+    fn slider(
+        &self,
+        name: &str,
+        min: f32,
+        max: f32,
+        value: f32,
+        readout: &Element,
+    ) -> anyhow::Result<HtmlInputElement> {
+        let slider: HtmlInputElement = self
+            .document
+            .create_element("input")
+            .map_err(js_error)?
+            .unchecked_into();
+        slider.set_type("range");
+        slider.set_min(&min.to_string());
+        slider.set_max(&max.to_string());
+        slider.set_step("any");
+        slider.set_value_as_number(value as f64);
+
+        let values = self.values.clone();
+        let name = name.to_owned();
+        let readout = readout.clone();
+        let input = slider.clone();
+        let wake = self.wake.clone();
+        let on_input = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+            let value = vec2(input.value_as_number() as f32, 0.0);
+            readout.set_text_content(Some(&format_value(value, false)));
+            values.borrow_mut().insert(name.clone(), value);
+            wake_up(&wake);
+        });
+        slider
+            .add_event_listener_with_callback("input", on_input.as_ref().unchecked_ref())
+            .map_err(js_error)?;
+        on_input.forget();
+
+        Ok(slider)
+    }
+
+    fn pad(
+        &self,
+        name: &str,
+        min: Vec2,
+        max: Vec2,
+        value: Vec2,
+        readout: &Element,
+    ) -> anyhow::Result<Element> {
+        let pad = self.document.create_element("div").map_err(js_error)?;
+        pad.set_class_name("inox2d-pad");
+        pad.set_attribute(
+            "style",
+            &format!(
+                "position: relative; width: {PAD_SIZE}px; height: {PAD_SIZE}px; \
+                 border: 1px solid currentColor; cursor: crosshair;"
+            ),
+        )
+        .map_err(js_error)?;
+
+        let dot = self.document.create_element("div").map_err(js_error)?;
+        place_dot(&dot, normalize(value, min, max));
+        pad.append_child(&dot).map_err(js_error)?;
+
+        let values = self.values.clone();
+        let name = name.to_owned();
+        let readout = readout.clone();
+        let wake = self.wake.clone();
+        let on_mouse = Closure::<dyn FnMut(_)>::new(move |event: MouseEvent| {
+            // Only while the primary button is held
+            if event.buttons() & 1 == 0 {
+                return;
+            }
+            event.prevent_default();
+
+            // Up is the maximum of the y axis
+            let t = vec2(
+                event.offset_x() as f32 / PAD_SIZE as f32,
+                1.0 - event.offset_y() as f32 / PAD_SIZE as f32,
+            )
+            .clamp(Vec2::ZERO, Vec2::ONE);
+            let value = min + t * (max - min);
+
+            place_dot(&dot, t);
+            readout.set_text_content(Some(&format_value(value, true)));
+            values.borrow_mut().insert(name.clone(), value);
+            wake_up(&wake);
+        });
+        for event in ["mousedown", "mousemove"] {
+            pad.add_event_listener_with_callback(event, on_mouse.as_ref().unchecked_ref())
+                .map_err(js_error)?;
+        }
+        on_mouse.forget();
+
+        Ok(pad)
+    }
+}
+
+fn wake_up(wake: &Option<EventLoopProxy<()>>) {
+    if let Some(wake) = wake {
+        // Only fails once the event loop is gone, and with it anything to render
+        let _ = wake.send_event(());
+    }
+}
+
+/// Moves the dot of a pad, `t` being normalized with up as the maximum.
+fn place_dot(dot: &Element, t: Vec2) {
+    let x = t.x.clamp(0.0, 1.0) as f64 * PAD_SIZE;
+    let y = (1.0 - t.y.clamp(0.0, 1.0)) as f64 * PAD_SIZE;
+    // The dot must not catch the pad's mouse events, or offsets become relative to it
+    let _ = dot.set_attribute(
+        "style",
+        &format!(
+            "position: absolute; left: {}px; top: {}px; width: 6px; height: 6px; \
+             border-radius: 50%; background: currentColor; pointer-events: none;",
+            x - 3.0,
+            y - 3.0
+        ),
+    );
+}
+
+/// Where `value` lies between `min` and `max`, centered on axes without any range.
+fn normalize(value: Vec2, min: Vec2, max: Vec2) -> Vec2 {
+    let range = max - min;
+    Vec2::select(
+        range.cmpeq(Vec2::ZERO),
+        Vec2::splat(0.5),
+        (value - min) / range,
+    )
+}
+
+fn format_value(value: Vec2, is_vec2: bool) -> String {
+    if is_vec2 {
+        format!("{:.2}, {:.2}", value.x, value.y)
+    } else {
+        format!("{:.2}", value.x)
+    }
+}
@@ -0,0 +1,76 @@
+//! Helpers shared by everything that drives puppet parameters.
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+use log::debug;
+
+/// One component of a parameter. 1D parameters only have an `X` axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParamAxis {
+    X,
+    Y,
+}
+
+impl ParamAxis {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "x" | "X" => Ok(Self::X),
+            "y" | "Y" => Ok(Self::Y),
+            _ => Err(anyhow!("unknown axis {s:?}, expected \"x\" or \"y\"")),
+        }
+    }
+}
+
+/// Writes a single axis of a parameter into `values`,
+/// starting from the parameter's default if it wasn't set yet.
+///
+/// Parameters the puppet doesn't have are skipped,
+/// so that the same mapping can be used with differently rigged puppets.
+pub fn set_axis(
+    puppet: &Puppet,
+    values: &mut HashMap<String, Vec2>,
+    name: &str,
+    axis: ParamAxis,
+    value: f32,
+) {
+    let Some(param) = puppet.parameters.get(name) else {
+        return;
+    };
+
+    let entry = values.entry(name.to_owned()).or_insert(param.defaults);
+    match axis {
+        ParamAxis::X => entry.x = value,
+        ParamAxis::Y => entry.y = value,
+    }
+}
+
+/// Reads a single axis of a parameter from `values`, or the parameter's default.
+pub fn get_axis(
+    puppet: &Puppet,
+    values: &HashMap<String, Vec2>,
+    name: &str,
+    axis: ParamAxis,
+) -> Option<f32> {
+    let value = match values.get(name) {
+        Some(value) => *value,
+        None => puppet.parameters.get(name)?.defaults,
+    };
+
+    Some(match axis {
+        ParamAxis::X => value.x,
+        ParamAxis::Y => value.y,
+    })
+}
+
+/// Sets every value on the puppet.
+/// Must be called between `begin_set_params` and `end_set_params`.
+pub fn apply(puppet: &mut Puppet, values: &HashMap<String, Vec2>) {
+    for (name, value) in values {
+        if let Err(e) = puppet.set_param(name, *value) {
+            debug!("{e}");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Measuring frame times, CPU and GPU time per frame, and what the scene costs to draw.
+
+use web_time::{Duration, Instant};
+
+use crate::capabilities::Capabilities;
+use crate::readback::{self, MapResult};
+
+/// Upper bounds of the frame time histogram's buckets, in milliseconds.
+pub const HISTOGRAM_BOUNDS: [f32; 6] = [8.0, 16.7, 33.3, 50.0, 100.0, f32::INFINITY];
+
+/// How often stats are reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What it takes to draw the scene, as far as this side of the renderer can tell.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SceneCost {
+    pub draw_calls: u32,
+    pub texture_bytes: u64,
+}
+
+/// Averages over the last reporting interval.
+#[derive(Debug, Clone, Default)]
+pub struct PerfStats {
+    pub fps: f32,
+    pub frame_ms: f32,
+    /// Frames per bucket of [`HISTOGRAM_BOUNDS`].
+    pub histogram: [u32; HISTOGRAM_BOUNDS.len()],
+    /// CPU time spent driving and applying parameters.
+    pub params_ms: f32,
+    /// CPU time spent encoding and submitting draws.
+    pub render_ms: f32,
+    /// GPU time spent rendering, if the device has timestamp queries.
+    pub gpu_ms: Option<f32>,
+    pub cost: SceneCost,
+}
+
+impl PerfStats {
+    /// A few lines for the HUD.
+    pub fn summary(&self) -> String {
+        let gpu = match self.gpu_ms {
+            Some(ms) => format!("{ms:.2} ms"),
+            None => "n/a".to_owned(),
+        };
+        let mut text = format!(
+            "{:.0} fps, {:.2} ms/frame\n\
+             params {:.2} ms, render {:.2} ms, gpu {gpu}\n\
+             {} draw calls, {:.1} MiB of textures\n",
+            self.fps,
+            self.frame_ms,
+            self.params_ms,
+            self.render_ms,
+            self.cost.draw_calls,
+            self.cost.texture_bytes as f32 / (1024.0 * 1024.0),
+        );
+
+        let most = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        let mut lower = 0.0;
+        for (bound, count) in HISTOGRAM_BOUNDS.iter().zip(self.histogram) {
+            let label = match bound.is_finite() {
+                true => format!("{lower:>4.0}-{bound:<4.0}ms"),
+                false => format!("{lower:>4.0}+     ms"),
+            };
+            let bar = "#".repeat((count * 20).div_ceil(most) as usize);
+            text.push_str(&format!("{label} {bar:<20} {count}\n"));
+            lower = *bound;
+        }
+        text
+    }
+}
+
+/// Measures GPU time between two points of the queue with timestamp queries.
+struct GpuTimer {
+    queries: wgpu::QuerySet,
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    /// Set while a measurement is being read back, as only one can be in flight.
+    mapped: Option<MapResult>,
+    started: bool,
+}
+
+impl GpuTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let queries = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("frame timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let buffer = |label, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: 2 * wgpu::QUERY_SIZE as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            queries,
+            resolve: buffer(
+                "timestamp resolve buffer",
+                wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            readback: buffer(
+                "timestamp readback buffer",
+                wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            ),
+            period: queue.get_timestamp_period(),
+            mapped: None,
+            started: false,
+        }
+    }
+
+    fn begin(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.mapped.is_some() {
+            return;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("timestamp encoder"),
+        });
+        encoder.write_timestamp(&self.queries, 0);
+        queue.submit(Some(encoder.finish()));
+        self.started = true;
+    }
+
+    fn end(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !std::mem::take(&mut self.started) {
+            return;
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("timestamp encoder"),
+        });
+        encoder.write_timestamp(&self.queries, 1);
+        encoder.resolve_query_set(&self.queries, 0..2, &self.resolve, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve, 0, &self.readback, 0, self.resolve.size());
+        queue.submit(Some(encoder.finish()));
+
+        self.mapped = Some(MapResult::map(self.readback.slice(..)));
+    }
+
+    /// Returns the last measurement in milliseconds, once read back.
+    fn poll(&mut self, device: &wgpu::Device) -> Option<f32> {
+        self.mapped.as_ref()?;
+        readback::poll_maps(device);
+
+        let mapped = self.mapped.as_ref()?.take()?;
+        self.mapped = None;
+        mapped.ok()?;
+
+        let ticks = {
+            let data = self.readback.slice(..).get_mapped_range();
+            let timestamp = |i: usize| {
+                let bytes = &data[i * 8..(i + 1) * 8];
+                u64::from_le_bytes(bytes.try_into().expect("timestamps are 8 bytes"))
+            };
+            timestamp(1).saturating_sub(timestamp(0))
+        };
+        self.readback.unmap();
+
+        Some(ticks as f32 * self.period / 1e6)
+    }
+}
+
+/// Collects timings frame by frame, and sums them up at regular intervals.
+pub struct PerfMonitor {
+    gpu: Option<GpuTimer>,
+    last_frame: Option<Instant>,
+    interval_start: Instant,
+    frame_times: Vec<f32>,
+    params: Duration,
+    render: Duration,
+    frames: u32,
+    gpu_ms: Vec<f32>,
+}
+
+impl PerfMonitor {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, capabilities: &Capabilities) -> Self {
+        Self {
+            gpu: capabilities
+                .gpu_timing()
+                .then(|| GpuTimer::new(device, queue)),
+            last_frame: None,
+            interval_start: Instant::now(),
+            frame_times: Vec::new(),
+            params: Duration::ZERO,
+            render: Duration::ZERO,
+            frames: 0,
+            gpu_ms: Vec::new(),
+        }
+    }
+
+    /// Starts timing a frame, `resumed` telling that nothing was rendered for a while.
+    pub fn begin_frame(&mut self, now: Instant, resumed: bool) {
+        // Time spent idle isn't time spent on a frame
+        if let (Some(last), false) = (self.last_frame, resumed) {
+            self.frame_times.push((now - last).as_secs_f32() * 1000.0);
+        }
+        self.last_frame = Some(now);
+        self.frames += 1;
+    }
+
+    pub fn add_params_time(&mut self, time: Duration) {
+        self.params += time;
+    }
+
+    pub fn add_render_time(&mut self, time: Duration) {
+        self.render += time;
+    }
+
+    /// Marks where GPU work for the frame starts.
+    pub fn begin_gpu(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.begin(device, queue);
+        }
+    }
+
+    /// Marks where GPU work for the frame ends.
+    pub fn end_gpu(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.end(device, queue);
+        }
+    }
+
+    /// Ends a frame, returning stats once a reporting interval is over.
+    pub fn end_frame(
+        &mut self,
+        device: &wgpu::Device,
+        cost: impl FnOnce() -> SceneCost,
+    ) -> Option<PerfStats> {
+        if let Some(ms) = self.gpu.as_mut().and_then(|gpu| gpu.poll(device)) {
+            self.gpu_ms.push(ms);
+        }
+
+        let elapsed = self.interval_start.elapsed();
+        if elapsed < REPORT_INTERVAL {
+            return None;
+        }
+
+        let mut histogram = [0; HISTOGRAM_BOUNDS.len()];
+        for &ms in &self.frame_times {
+            let bucket = HISTOGRAM_BOUNDS.iter().position(|&bound| ms < bound);
+            histogram[bucket.unwrap_or(HISTOGRAM_BOUNDS.len() - 1)] += 1;
+        }
+
+        let frames = self.frames.max(1) as f32;
+        let stats = PerfStats {
+            fps: self.frames as f32 / elapsed.as_secs_f32(),
+            frame_ms: mean(&self.frame_times).unwrap_or(0.0),
+            histogram,
+            params_ms: self.params.as_secs_f32() * 1000.0 / frames,
+            render_ms: self.render.as_secs_f32() * 1000.0 / frames,
+            gpu_ms: mean(&self.gpu_ms),
+            cost: cost(),
+        };
+
+        self.interval_start = Instant::now();
+        self.frame_times.clear();
+        self.params = Duration::ZERO;
+        self.render = Duration::ZERO;
+        self.frames = 0;
+        self.gpu_ms.clear();
+        Some(stats)
+    }
+}
+
+fn mean(values: &[f32]) -> Option<f32> {
+    (!values.is_empty()).then(|| values.iter().sum::<f32>() / values.len() as f32)
+}
@@ -0,0 +1,77 @@
+//! Showing frame timing stats over the canvas, or in the log when there is no page to show them.
+
+#[cfg(target_arch = "wasm32")]
+use anyhow::Context;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::JsCast;
+#[cfg(target_arch = "wasm32")]
+use web_sys::{HtmlCanvasElement, HtmlElement};
+
+use crate::perf::PerfStats;
+
+#[cfg_attr(not(target_arch = "wasm32"), derive(Default))]
+pub struct PerfHud {
+    #[cfg(target_arch = "wasm32")]
+    root: HtmlElement,
+    #[cfg(target_arch = "wasm32")]
+    canvas: HtmlCanvasElement,
+}
+
+impl PerfHud {
+    /// Creates an empty overlay in the canvas' top left corner.
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(canvas: &HtmlCanvasElement) -> anyhow::Result<Self> {
+        use crate::loader::js_error;
+
+        let document = web_sys::window()
+            .and_then(|win| win.document())
+            .context("no document")?;
+
+        let root: HtmlElement = document
+            .create_element("pre")
+            .map_err(js_error)?
+            .unchecked_into();
+        root.set_class_name("inox2d-hud");
+        root.set_attribute(
+            "style",
+            "position: absolute; margin: 0; padding: 4px; pointer-events: none; \
+             font: 11px monospace; color: #fff; background: rgba(0, 0, 0, 0.6);",
+        )
+        .map_err(js_error)?;
+        root.set_text_content(Some("measuring..."));
+        canvas.after_with_node_1(&root).map_err(js_error)?;
+
+        let hud = Self {
+            root,
+            canvas: canvas.clone(),
+        };
+        hud.place();
+        Ok(hud)
+    }
+
+    pub fn show(&self, stats: &PerfStats) {
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.place();
+            self.root.set_text_content(Some(&stats.summary()));
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        log::info!("{}", stats.summary());
+    }
+
+    /// Follows the canvas, which shares the overlay's offset parent.
+    #[cfg(target_arch = "wasm32")]
+    fn place(&self) {
+        let style = self.root.style();
+        let _ = style.set_property("left", &format!("{}px", self.canvas.offset_left()));
+        let _ = style.set_property("top", &format!("{}px", self.canvas.offset_top()));
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for PerfHud {
+    fn drop(&mut self) {
+        self.root.remove();
+    }
+}
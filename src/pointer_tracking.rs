@@ -0,0 +1,106 @@
+//! Makes the selected puppet look at the mouse cursor.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+
+use crate::params::{set_axis, ParamAxis};
+
+/// Default mapping, using the parameter names of the Inochi2D example puppets.
+pub const DEFAULT_MAPPING: &str = r#"{
+    "smoothing": 0.15,
+    "bindings": [
+        { "param": "Head:: Yaw-Pitch", "source": "x", "axis": "x", "range": [-1, 1] },
+        { "param": "Head:: Yaw-Pitch", "source": "y", "axis": "y", "range": [1, -1] },
+        { "param": "Body:: Yaw-Pitch", "source": "x", "axis": "x", "range": [-0.5, 0.5] },
+        { "param": "Body:: Yaw-Pitch", "source": "y", "axis": "y", "range": [0.3, -0.3] },
+        { "param": "Eyes:: Yaw-Pitch", "source": "x", "axis": "x", "range": [-1, 1] },
+        { "param": "Eyes:: Yaw-Pitch", "source": "y", "axis": "y", "range": [1, -1] }
+    ]
+}"#;
+
+/// Drives one axis of a parameter from one axis of the cursor.
+pub struct PointerBinding {
+    pub param: String,
+    /// Cursor axis, -1 to 1 from left to right and from top to bottom.
+    pub source: ParamAxis,
+    pub axis: ParamAxis,
+    /// Parameter values at the -1 and 1 ends of the cursor axis.
+    pub range: (f32, f32),
+}
+
+pub struct PointerTracker {
+    pub bindings: Vec<PointerBinding>,
+    /// Time constant of the exponential smoothing, in seconds.
+    pub smoothing: f32,
+    smoothed: Vec2,
+}
+
+impl PointerTracker {
+    /// Parses a mapping table, see [`DEFAULT_MAPPING`] for the format.
+    pub fn from_json(src: &str) -> anyhow::Result<Self> {
+        let root = json::parse(src)?;
+
+        let bindings = root["bindings"]
+            .members()
+            .map(|binding| {
+                let param = binding["param"]
+                    .as_str()
+                    .context("binding without a param")?
+                    .to_owned();
+                let source = ParamAxis::parse(binding["source"].as_str().unwrap_or("x"))?;
+                let axis = ParamAxis::parse(binding["axis"].as_str().unwrap_or("x"))?;
+                let range = match (binding["range"][0].as_f32(), binding["range"][1].as_f32()) {
+                    (Some(min), Some(max)) => (min, max),
+                    (None, None) => (-1.0, 1.0),
+                    _ => return Err(anyhow!("invalid range for {param:?}")),
+                };
+
+                Ok(PointerBinding {
+                    param,
+                    source,
+                    axis,
+                    range,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            bindings,
+            smoothing: root["smoothing"].as_f32().unwrap_or(0.15),
+            smoothed: Vec2::ZERO,
+        })
+    }
+
+    /// Moves towards the cursor, given relative to the puppet and normalized to [-1, 1].
+    pub fn update(&mut self, target: Vec2, dt: f32) {
+        let target = target.clamp(Vec2::NEG_ONE, Vec2::ONE);
+        let t = if self.smoothing > 0.0 {
+            1.0 - (-dt / self.smoothing).exp()
+        } else {
+            1.0
+        };
+        self.smoothed += (target - self.smoothed) * t;
+    }
+
+    pub fn write(&self, puppet: &Puppet, values: &mut HashMap<String, Vec2>) {
+        for binding in &self.bindings {
+            let input = match binding.source {
+                ParamAxis::X => self.smoothed.x,
+                ParamAxis::Y => self.smoothed.y,
+            };
+            let (min, max) = binding.range;
+            let value = min + (input + 1.0) / 2.0 * (max - min);
+
+            set_axis(puppet, values, &binding.param, binding.axis, value);
+        }
+    }
+}
+
+impl Default for PointerTracker {
+    fn default() -> Self {
+        Self::from_json(DEFAULT_MAPPING).expect("default pointer mapping is valid")
+    }
+}
@@ -0,0 +1,473 @@
+//! Several puppets sharing one surface, each with its own transform and parameters.
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use glam::{UVec2, Vec2};
+use inox2d::math::camera::Camera;
+use inox2d::nodes::node_data::InoxData;
+use inox2d::{model::Model, render::wgpu::Renderer};
+use log::warn;
+
+use crate::background::Background;
+use crate::capabilities::texture_size;
+use crate::compositor::Compositor;
+use crate::perf::SceneCost;
+use crate::readback::{self, MapResult};
+
+/// Placement of a puppet in the scene, in world units.
+#[derive(Debug, Clone, Copy)]
+pub struct PuppetTransform {
+    pub position: Vec2,
+    pub scale: f32,
+    pub rotation: f32,
+    /// Puppets with a higher z are drawn on top.
+    pub z: i32,
+}
+
+impl Default for PuppetTransform {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            scale: 1.0,
+            rotation: 0.0,
+            z: 0,
+        }
+    }
+}
+
+impl PuppetTransform {
+    /// Folds this transform into `camera`, giving the camera the puppet is rendered with.
+    ///
+    /// inox2d rotates around the origin after translating, so the offset is rotated back
+    /// for the puppet to turn in place rather than orbit the origin.
+    fn fold_into(&self, camera: &Camera, folded: &mut Camera) {
+        let offset = Vec2::from_angle(-self.rotation).rotate(camera.position + self.position);
+        folded.position = offset / self.scale;
+        folded.rotation = camera.rotation + self.rotation;
+        folded.scale = camera.scale * self.scale;
+    }
+}
+
+/// The texture a puppet is rendered into before being composited.
+struct Target {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Target {
+    fn new(
+        device: &wgpu::Device,
+        compositor: &Compositor,
+        format: wgpu::TextureFormat,
+        size: UVec2,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("puppet target"),
+            size: wgpu::Extent3d {
+                width: size.x.max(1),
+                height: size.y.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = compositor.bind(device, &view);
+
+        Self {
+            texture,
+            view,
+            bind_group,
+        }
+    }
+}
+
+pub struct ScenePuppet {
+    pub id: u32,
+    /// Kept whole rather than just the puppet, to recreate the renderer if the device is lost.
+    pub model: Model,
+    pub transform: PuppetTransform,
+    /// Parameter values applied on every frame.
+    pub params: HashMap<String, Vec2>,
+    renderer: Renderer,
+    target: Target,
+}
+
+/// An in-flight readback of the pixel under the cursor in every puppet's target.
+struct Pick {
+    buffer: wgpu::Buffer,
+    /// Puppet ids in the order their pixels were copied to the buffer, bottom to top.
+    ids: Vec<u32>,
+    mapped: MapResult,
+}
+
+pub struct PuppetScene {
+    puppets: Vec<ScenePuppet>,
+    next_id: u32,
+    pub selected: Option<u32>,
+
+    background: Background,
+    /// The uploaded background image, bound for compositing.
+    background_image: Option<(wgpu::Texture, wgpu::BindGroup)>,
+
+    compositor: Compositor,
+    /// Format of the puppets' own targets, never sRGB since inox2d outputs sRGB values as is.
+    format: wgpu::TextureFormat,
+    /// Format of the target everything is composited into.
+    target_format: wgpu::TextureFormat,
+    size: UVec2,
+    pick: Option<Pick>,
+}
+
+impl PuppetScene {
+    /// Creates an empty scene rendering into targets of the given format, sRGB or not.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, size: UVec2) -> Self {
+        Self {
+            puppets: Vec::new(),
+            next_id: 0,
+            selected: None,
+            background: Background::default(),
+            background_image: None,
+            compositor: Compositor::new(device, format),
+            format: format.remove_srgb_suffix(),
+            target_format: format,
+            size,
+            pick: None,
+        }
+    }
+
+    /// Adds a puppet on top of the others and selects it, returning its id.
+    pub fn add(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, model: Model) -> u32 {
+        let renderer = Renderer::new(device, queue, self.format, &model, self.size);
+        let target = Target::new(device, &self.compositor, self.format, self.size);
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let z = self
+            .puppets
+            .iter()
+            .map(|p| p.transform.z + 1)
+            .max()
+            .unwrap_or(0);
+        self.puppets.push(ScenePuppet {
+            id,
+            model,
+            transform: PuppetTransform {
+                z,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            renderer,
+            target,
+        });
+        self.selected = Some(id);
+
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.puppets.len();
+        self.puppets.retain(|p| p.id != id);
+        if self.selected == Some(id) {
+            self.selected = None;
+        }
+        self.puppets.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.puppets.clear();
+        self.selected = None;
+        self.pick = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.puppets.is_empty()
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut ScenePuppet> {
+        self.puppets.iter_mut().find(|p| p.id == id)
+    }
+
+    pub fn selected(&self) -> Option<&ScenePuppet> {
+        self.puppets.iter().find(|p| Some(p.id) == self.selected)
+    }
+
+    pub fn selected_mut(&mut self) -> Option<&mut ScenePuppet> {
+        let selected = self.selected;
+        self.puppets.iter_mut().find(|p| Some(p.id) == selected)
+    }
+
+    pub fn puppets_mut(&mut self) -> impl Iterator<Item = &mut ScenePuppet> {
+        self.puppets.iter_mut()
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    /// Sets what puppets are drawn over, uploading images to the GPU.
+    pub fn set_background(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        background: Background,
+    ) -> anyhow::Result<()> {
+        self.background_image = match &background {
+            Background::Image { size, pixels } => {
+                let max = device.limits().max_texture_dimension_2d;
+                if size.min_element() == 0 || size.max_element() > max {
+                    return Err(anyhow!(
+                        "background image is {size}, the limit is {max} pixels"
+                    ));
+                }
+
+                let extent = wgpu::Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                };
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("background image"),
+                    size: extent,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                queue.write_texture(
+                    texture.as_image_copy(),
+                    pixels,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(size.x * 4),
+                        rows_per_image: None,
+                    },
+                    extent,
+                );
+
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = self.compositor.bind(device, &view);
+                Some((texture, bind_group))
+            }
+            _ => None,
+        };
+
+        self.background = background;
+        Ok(())
+    }
+
+    /// Recreates every GPU resource on a new device, after the previous one was lost.
+    pub fn recreate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<()> {
+        self.compositor = Compositor::new(device, self.target_format);
+        self.pick = None;
+        for p in &mut self.puppets {
+            p.renderer = Renderer::new(device, queue, self.format, &p.model, self.size);
+            p.target = Target::new(device, &self.compositor, self.format, self.size);
+        }
+
+        let background = std::mem::take(&mut self.background);
+        self.set_background(device, queue, background)
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: UVec2) {
+        self.size = size;
+        for p in &mut self.puppets {
+            p.renderer.resize(size);
+            p.target = Target::new(device, &self.compositor, self.format, size);
+        }
+    }
+
+    /// Renders every puppet seen through `camera` into `view`, from the lowest z to the highest.
+    ///
+    /// Without `background`, only the puppets are drawn, over transparency.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        view: &wgpu::TextureView,
+        background: bool,
+    ) {
+        self.puppets.sort_by_key(|p| p.transform.z);
+
+        for p in &mut self.puppets {
+            p.transform.fold_into(camera, &mut p.renderer.camera);
+
+            p.renderer
+                .render(queue, device, &p.model.puppet, &p.target.view);
+        }
+
+        // The target holds premultiplied colors, and so must the clear color
+        let clear_color = if background {
+            self.background.clear_color(self.target_format.is_srgb())
+        } else {
+            wgpu::Color::TRANSPARENT
+        };
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("composite encoder"),
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("composite pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear_color),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            if let (true, Some((_, image))) = (background, &self.background_image) {
+                self.compositor.draw(&mut pass, image);
+            }
+            for p in &self.puppets {
+                self.compositor.draw(&mut pass, &p.target.bind_group);
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+
+    /// Starts looking up which puppet is under the given pixel, see [`Self::poll_pick`].
+    ///
+    /// Reads the targets as last rendered, so puppets are hit where they were drawn,
+    /// with their transforms folded in as in [`Self::render`].
+    pub fn request_pick(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pixel: UVec2) {
+        if self.puppets.is_empty() || pixel.x >= self.size.x || pixel.y >= self.size.y {
+            return;
+        }
+        self.puppets.sort_by_key(|p| p.transform.z);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("pick buffer"),
+            size: 4 * self.puppets.len() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("pick encoder"),
+        });
+        for (i, p) in self.puppets.iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture: &p.target.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: pixel.x,
+                        y: pixel.y,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: 4 * i as u64,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let mapped = MapResult::map(buffer.slice(..));
+
+        self.pick = Some(Pick {
+            buffer,
+            ids: self.puppets.iter().map(|p| p.id).collect(),
+            mapped,
+        });
+    }
+
+    /// Estimates draw calls and texture memory, counting a draw per part and compositing.
+    pub fn cost(&self) -> SceneCost {
+        let target_bytes = self.size.x as u64 * self.size.y as u64 * 4;
+        let mut cost = SceneCost::default();
+
+        for p in &self.puppets {
+            let parts = p
+                .model
+                .puppet
+                .nodes
+                .arena
+                .iter()
+                .filter(|node| matches!(node.get().data, InoxData::Part(_)))
+                .count();
+            cost.draw_calls += parts as u32 + 1;
+
+            let textures: u64 = p
+                .model
+                .textures
+                .iter()
+                .filter_map(|texture| texture_size(&texture.data))
+                .map(|(width, height)| width as u64 * height as u64 * 4)
+                .sum();
+            cost.texture_bytes += textures + target_bytes;
+        }
+
+        if let Some((texture, _)) = &self.background_image {
+            let size = texture.size();
+            cost.draw_calls += 1;
+            cost.texture_bytes += size.width as u64 * size.height as u64 * 4;
+        }
+        cost
+    }
+
+    pub fn is_picking(&self) -> bool {
+        self.pick.is_some()
+    }
+
+    /// Returns the topmost puppet that was under the picked pixel, once the readback is done.
+    ///
+    /// Puppets removed in the meantime aren't hit, and a failed readback hits nothing.
+    pub fn poll_pick(&mut self, device: &wgpu::Device) -> Option<Option<u32>> {
+        self.pick.as_ref()?;
+        readback::poll_maps(device);
+
+        let mapped = self.pick.as_ref()?.mapped.take()?;
+        let pick = self.pick.take()?;
+        if let Err(e) = mapped {
+            warn!("couldn't read the picked pixel back: {e}");
+            return Some(None);
+        }
+
+        let hit = {
+            let data = pick.buffer.slice(..).get_mapped_range();
+            // Alpha is the last byte for both RGBA and BGRA targets
+            data.chunks_exact(4)
+                .zip(&pick.ids)
+                .filter(|(texel, _)| texel[3] > 0)
+                .map(|(_, id)| *id)
+                .filter(|id| self.puppets.iter().any(|p| p.id == *id))
+                .last()
+        };
+        pick.buffer.unmap();
+
+        Some(hit)
+    }
+}
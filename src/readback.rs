@@ -0,0 +1,203 @@
+//! Copying rendered textures back to the CPU.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use futures_channel::oneshot;
+
+/// Where the outcome of mapping a buffer lands, for the render loop to pick up.
+#[derive(Clone, Default)]
+pub struct MapResult(Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>);
+
+impl MapResult {
+    /// Starts mapping `slice` for reading.
+    pub fn map(slice: wgpu::BufferSlice) -> Self {
+        let mapped = Self::default();
+        let result = mapped.0.clone();
+        slice.map_async(wgpu::MapMode::Read, move |res| {
+            *result.lock().unwrap() = Some(res)
+        });
+        mapped
+    }
+
+    /// Takes the outcome, once the mapping is done.
+    pub fn take(&self) -> Option<Result<(), wgpu::BufferAsyncError>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Runs the callbacks of finished mappings, as native backends only call them when polled.
+pub fn poll_maps(device: &wgpu::Device) {
+    device.poll(wgpu::Maintain::Poll);
+}
+
+/// A texture copied into a buffer, waiting to be mapped.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    size: wgpu::Extent3d,
+    bytes_per_row: u32,
+    bgra: bool,
+}
+
+impl Readback {
+    /// Copies a 2D texture with 4 bytes per pixel into a mappable buffer.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<Self> {
+        let bgra = match texture.format() {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => return Err(anyhow!("can't read back {format:?} textures")),
+        };
+
+        let size = texture.size();
+        // Buffer rows must be aligned for texture copies
+        let bytes_per_row = (size.width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback buffer"),
+            size: bytes_per_row as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        Ok(Self {
+            buffer,
+            size,
+            bytes_per_row,
+            bgra,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.size.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.size.height
+    }
+
+    /// Starts mapping the buffer, calling `callback` once it can be read.
+    pub fn map(&self, callback: impl FnOnce(Result<(), wgpu::BufferAsyncError>) + Send + 'static) {
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, callback);
+    }
+
+    /// Starts mapping the buffer, for the render loop to poll.
+    pub fn map_async(&self) -> MapResult {
+        MapResult::map(self.buffer.slice(..))
+    }
+
+    /// Returns tightly packed RGBA rows, once mapped.
+    pub fn into_rgba(self) -> Vec<u8> {
+        let row_len = self.size.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row_len * self.size.height as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks(self.bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..row_len]);
+            }
+        }
+        self.buffer.unmap();
+
+        if self.bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        pixels
+    }
+}
+
+/// Reads a 2D texture with 4 bytes per pixel, returning tightly packed RGBA rows.
+pub async fn read_rgba(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> anyhow::Result<Vec<u8>> {
+    let readback = Readback::new(device, queue, texture)?;
+
+    let (tx, rx) = oneshot::channel();
+    readback.map(move |res| {
+        let _ = tx.send(res);
+    });
+    // Browsers map buffers on their own, native backends need to be polled
+    #[cfg(not(target_arch = "wasm32"))]
+    device.poll(wgpu::Maintain::Wait);
+    rx.await??;
+
+    Ok(readback.into_rgba())
+}
+
+/// Converts straight alpha RGBA pixels, as decoded from PNG, to premultiplied ones.
+pub fn premultiply(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        for c in &mut pixel[..3] {
+            *c = ((*c as u32 * alpha + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Converts premultiplied RGBA pixels to the straight alpha that PNG expects.
+pub fn unpremultiply(rgba: &mut [u8]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha != 0 && alpha != 255 {
+            for c in &mut pixel[..3] {
+                *c = ((*c as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// Flattens premultiplied RGBA pixels over an opaque color.
+pub fn flatten(rgba: &mut [u8], background: [u8; 3]) {
+    for pixel in rgba.chunks_exact_mut(4) {
+        let transparency = 255 - pixel[3] as u32;
+        for (c, bg) in pixel[..3].iter_mut().zip(background) {
+            *c = (*c as u32 + (bg as u32 * transparency + 127) / 255).min(255) as u8;
+        }
+        pixel[3] = 255;
+    }
+}
+
+/// Encodes premultiplied RGBA rows as a PNG, which has straight alpha.
+pub fn encode_premultiplied_png(width: u32, height: u32, rgba: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut straight = rgba.to_vec();
+    unpremultiply(&mut straight);
+    encode_png(width, height, &straight)
+}
+
+/// Encodes tightly packed RGBA rows as a PNG.
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(rgba)?;
+    }
+    Ok(png)
+}
@@ -0,0 +1,326 @@
+//! Recording frames from the render loop into animated GIF or APNG clips.
+
+use anyhow::anyhow;
+use glam::{uvec2, UVec2};
+use log::{info, warn};
+use std::collections::VecDeque;
+
+use crate::capture::CaptureOptions;
+use crate::readback::{self, MapResult, Readback};
+
+/// Longest clip that can be recorded, in seconds.
+pub const MAX_DURATION: f32 = 60.0;
+/// Memory that frames may take until they are encoded, as they're all kept raw until then.
+pub const MAX_FRAME_BYTES: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    Gif,
+    Apng,
+}
+
+impl VideoFormat {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "gif" => Ok(Self::Gif),
+            "apng" | "png" => Ok(Self::Apng),
+            _ => Err(anyhow!("unknown video format {s:?}")),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Apng => "png",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Gif => "image/gif",
+            Self::Apng => "image/apng",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordOptions {
+    pub format: VideoFormat,
+    /// Output frame rate, independent of how often the display refreshes.
+    pub fps: f32,
+    /// Output size relative to the window.
+    pub scale: f32,
+    /// Steps time by exactly one output frame per rendered frame, instead of following the clock.
+    ///
+    /// Clips come out identical however slow rendering is, but play at the wrong speed on screen.
+    pub deterministic: bool,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        Self {
+            format: VideoFormat::Gif,
+            fps: 30.0,
+            scale: 1.0,
+            deterministic: false,
+        }
+    }
+}
+
+type Callback = Box<dyn FnOnce(anyhow::Result<Vec<u8>>)>;
+
+struct PendingFrame {
+    readback: Readback,
+    mapped: MapResult,
+    /// How many output frames this frame lasts.
+    repeat: u32,
+}
+
+struct Frame {
+    rgba: Vec<u8>,
+    repeat: u32,
+}
+
+pub struct Recorder {
+    pub options: RecordOptions,
+    /// Time since the last output frame, in seconds.
+    since_frame: f32,
+    pending: VecDeque<PendingFrame>,
+    frames: Vec<Frame>,
+    /// Size of every frame, set by the first one.
+    size: UVec2,
+    /// Output frames and raw bytes queued so far, to stay within the limits.
+    frame_count: u32,
+    frame_bytes: u64,
+    error: Option<anyhow::Error>,
+    on_stop: Option<Callback>,
+}
+
+impl Recorder {
+    pub fn new(options: RecordOptions) -> anyhow::Result<Self> {
+        if !(options.fps > 0.0 && options.fps <= 100.0) {
+            return Err(anyhow!(
+                "frame rate must be between 0 and 100, not {}",
+                options.fps
+            ));
+        }
+
+        Ok(Self {
+            options,
+            // Record the very first frame
+            since_frame: 1.0 / options.fps,
+            pending: VecDeque::new(),
+            frames: Vec::new(),
+            size: UVec2::ZERO,
+            frame_count: 0,
+            frame_bytes: 0,
+            error: None,
+            on_stop: None,
+        })
+    }
+
+    /// The time step to force on the scene, in deterministic mode.
+    pub fn fixed_step(&self) -> Option<f32> {
+        self.options.deterministic.then(|| 1.0 / self.options.fps)
+    }
+
+    pub fn capture_options(&self) -> CaptureOptions {
+        CaptureOptions {
+            scale: self.options.scale,
+            // Clips show the background, and stay transparent where it is
+            transparent: false,
+        }
+    }
+
+    /// Advances the clock by `dt` seconds, returning how many output frames are due.
+    pub fn frames_due(&mut self, dt: f32) -> u32 {
+        // Frames after an error would be thrown away anyway
+        if self.on_stop.is_some() || self.error.is_some() {
+            return 0;
+        }
+
+        self.since_frame += dt;
+        // Tolerate rounding errors, so that deterministic steps always add up to one frame
+        let due = (self.since_frame * self.options.fps + 1e-3).floor();
+        self.since_frame -= due / self.options.fps;
+        due as u32
+    }
+
+    /// Whether frames still have to be rendered or read back, which stops after an error.
+    pub fn needs_frames(&self) -> bool {
+        !self.pending.is_empty() || self.is_recording()
+    }
+
+    /// Queues a rendered frame lasting `repeat` output frames.
+    ///
+    /// Fails the recording once it gets longer than [`MAX_DURATION`], its frames take more
+    /// than [`MAX_FRAME_BYTES`], or they change size.
+    pub fn push(&mut self, readback: anyhow::Result<Readback>, repeat: u32) {
+        let readback = match readback {
+            Ok(readback) => readback,
+            Err(e) => return self.fail(e),
+        };
+
+        let size = uvec2(readback.width(), readback.height());
+        if self.frame_count == 0 {
+            self.size = size;
+        } else if size != self.size {
+            return self.fail(anyhow!(
+                "recording stopped, the window was resized from {} to {size} and clips can't \
+                 change size",
+                self.size
+            ));
+        }
+
+        self.frame_count = self.frame_count.saturating_add(repeat);
+        self.frame_bytes += size.x as u64 * size.y as u64 * 4;
+        let duration = self.frame_count as f32 / self.options.fps;
+        if duration > MAX_DURATION || self.frame_bytes > MAX_FRAME_BYTES {
+            return self.fail(anyhow!(
+                "recording stopped after {duration:.1} s, clips are limited to {MAX_DURATION} s \
+                 and {} MiB of frames, try a smaller scale or frame rate",
+                MAX_FRAME_BYTES >> 20
+            ));
+        }
+
+        let mapped = readback.map_async();
+
+        self.pending.push_back(PendingFrame {
+            readback,
+            mapped,
+            repeat,
+        });
+    }
+
+    /// Collects the frames whose readback landed, in order.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        if self.pending.is_empty() {
+            return;
+        }
+        readback::poll_maps(device);
+
+        while let Some(frame) = self.pending.front() {
+            let Some(mapped) = frame.mapped.take() else {
+                break;
+            };
+            let frame = self.pending.pop_front().expect("front frame exists");
+
+            if let Err(e) = mapped {
+                self.fail(e.into());
+                break;
+            }
+
+            self.frames.push(Frame {
+                rgba: frame.readback.into_rgba(),
+                repeat: frame.repeat,
+            });
+        }
+    }
+
+    /// Stops recording frames, keeping the first error for [`Self::finish`] to report.
+    fn fail(&mut self, e: anyhow::Error) {
+        warn!("{e}");
+        self.error.get_or_insert(e);
+        self.pending.clear();
+        self.frames.clear();
+    }
+
+    /// Drops frames being read back, e.g. because their device was lost.
+    ///
+    /// The clip goes on without them, the frames around the gap lasting no longer.
+    pub fn discard_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Stops recording, `callback` receiving the encoded clip once every frame is read back.
+    pub fn stop(&mut self, callback: impl FnOnce(anyhow::Result<Vec<u8>>) + 'static) {
+        self.on_stop = Some(Box::new(callback));
+    }
+
+    /// Whether new frames are still recorded, until stopped or failed.
+    pub fn is_recording(&self) -> bool {
+        self.on_stop.is_none() && self.error.is_none()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.on_stop.is_some() && self.pending.is_empty()
+    }
+
+    /// Encodes the clip and hands it to the callback given to [`Self::stop`].
+    pub fn finish(mut self) {
+        let Some(callback) = self.on_stop.take() else {
+            return;
+        };
+
+        let clip = match self.error.take() {
+            Some(e) => Err(e),
+            None if self.frames.is_empty() => Err(anyhow!("no frames were recorded")),
+            None => self.encode(),
+        };
+        callback(clip);
+    }
+
+    fn encode(mut self) -> anyhow::Result<Vec<u8>> {
+        for frame in &mut self.frames {
+            readback::unpremultiply(&mut frame.rgba);
+        }
+
+        let clip = match self.options.format {
+            VideoFormat::Gif => self.encode_gif()?,
+            VideoFormat::Apng => self.encode_apng()?,
+        };
+        info!(
+            "recorded {} frames at {}x{}, {} KiB",
+            self.frames.len(),
+            self.size.x,
+            self.size.y,
+            clip.len() / 1024
+        );
+        Ok(clip)
+    }
+
+    fn encode_gif(&mut self) -> anyhow::Result<Vec<u8>> {
+        let (width, height) = (u16::try_from(self.size.x)?, u16::try_from(self.size.y)?);
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, width, height, &[])?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+
+            // GIF delays are in hundredths of a second, rounded so as not to drift
+            let mut time = 0.0;
+            let mut written = 0;
+            for frame in &mut self.frames {
+                time += frame.repeat as f64 * 100.0 / self.options.fps as f64;
+                let end = time.round() as u64;
+
+                let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut frame.rgba, 10);
+                gif_frame.delay = u16::try_from(end - written).unwrap_or(u16::MAX);
+                gif_frame.dispose = gif::DisposalMethod::Background;
+                encoder.write_frame(&gif_frame)?;
+                written = end;
+            }
+        }
+        Ok(gif)
+    }
+
+    fn encode_apng(&self) -> anyhow::Result<Vec<u8>> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, self.size.x, self.size.y);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(self.frames.len() as u32, 0)?;
+            encoder.set_dispose_op(png::DisposeOp::Background)?;
+
+            let mut writer = encoder.write_header()?;
+            // Delays are fractions of a second, in milliseconds to allow any frame rate
+            for frame in &self.frames {
+                let delay = (frame.repeat as f32 * 1000.0 / self.options.fps).round();
+                writer.set_frame_delay(delay.min(u16::MAX as f32) as u16, 1000)?;
+                writer.write_image_data(&frame.rgba)?;
+            }
+            writer.finish()?;
+        }
+        Ok(png)
+    }
+}
@@ -1,26 +1,67 @@
 //! A nice scene controller to smoothly move around in the window.
 
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
 use glam::{vec2, Vec2};
 use inox2d::math::camera::Camera;
-use web_time::Instant;
-use winit::event::{ElementState, MouseScrollDelta, WindowEvent};
+use web_time::{Duration, Instant};
+use winit::event::{ElementState, MouseScrollDelta, TouchPhase, WindowEvent};
 use winit::window::Window;
 
+/// How quickly a fling slows down, as the exponential decay rate of its speed per second.
+const FLING_FRICTION: f32 = 4.0;
+/// Speed below which a fling stops, in pixels per second.
+const MIN_FLING_SPEED: f32 = 20.0;
+/// How long a finger may rest before lifting and still fling, in seconds.
+const FLING_WINDOW: f32 = 0.1;
+
+/// Turns a movement on screen into the matching movement of the world, for a camera with
+/// the given scale and rotation, since inox2d rotates the world before scaling it.
+pub fn screen_to_world(delta: Vec2, scale: Vec2, rotation: f32) -> Vec2 {
+    Vec2::from_angle(-rotation).rotate(delta / scale)
+}
+
+/// Where a world position ends up on screen, relative to the center of the viewport.
+pub fn world_to_screen(position: Vec2, camera: &Camera) -> Vec2 {
+    Vec2::from_angle(camera.rotation).rotate(position + camera.position) * camera.scale
+}
+
 pub struct ExampleSceneController {
     // for camera position and mouse interactions
     camera_pos: Vec2,
     mouse_pos: Vec2,
     mouse_pos_held: Vec2,
+    // surface pixels per pixel of cursor movement, when the surface isn't at the display's density
+    pub cursor_scale: f32,
     mouse_state: ElementState,
+    // position of the object being dragged instead of the camera, when pressed
+    grabbed_pos: Option<Vec2>,
 
     // for smooth scrolling
     pub scroll_speed: f32,
     hard_scale: Vec2,
+    // screen position kept still while zooming, instead of the center
+    zoom_anchor: Option<Vec2>,
+    // surface size, to find the center
+    pub viewport: Vec2,
+
+    // for touch interactions, fingers by id
+    touches: HashMap<u64, Vec2>,
+    // movement and rotation since the last frame
+    touch_pan: Vec2,
+    touch_rotation: f32,
+    // pan speed while one finger moves, then after lifting it, in pixels per second
+    pan_velocity: Vec2,
+    last_touch_move: Instant,
+    fling: Vec2,
 
     // for FPS-independent interactions
     start: Instant,
     prev_elapsed: f32,
     current_elapsed: f32,
+    // advances time by a fixed amount per frame instead of following the clock
+    fixed_step: Option<f32>,
 }
 
 impl ExampleSceneController {
@@ -29,35 +70,92 @@ impl ExampleSceneController {
             camera_pos: camera.position,
             mouse_pos: Vec2::default(),
             mouse_pos_held: Vec2::default(),
+            cursor_scale: 1.0,
             mouse_state: ElementState::Released,
+            grabbed_pos: None,
             scroll_speed,
             hard_scale: camera.scale,
+            zoom_anchor: None,
+            viewport: Vec2::ONE,
+            touches: HashMap::new(),
+            touch_pan: Vec2::ZERO,
+            touch_rotation: 0.0,
+            pan_velocity: Vec2::ZERO,
+            last_touch_move: Instant::now(),
+            fling: Vec2::ZERO,
             start: Instant::now(),
             prev_elapsed: 0.0,
             current_elapsed: 0.0,
+            fixed_step: None,
         }
     }
 
+    /// Syncs the controller with a camera that was moved from outside.
+    pub fn reset(&mut self, camera: &Camera) {
+        self.camera_pos = camera.position;
+        self.hard_scale = camera.scale;
+        self.zoom_anchor = None;
+        self.fling = Vec2::ZERO;
+    }
+
     pub fn update(&mut self, camera: &mut Camera) {
         // Smooth scrolling
         let time_delta = self.current_elapsed - self.prev_elapsed;
+        let (old_scale, old_rotation) = (camera.scale, camera.rotation);
         camera.scale = camera.scale + time_delta.powf(0.6) * (self.hard_scale - camera.scale);
+        camera.rotation += std::mem::take(&mut self.touch_rotation);
+
+        // Keep the world point under the anchor where it is on screen
+        if let Some(anchor) = self.zoom_anchor {
+            let offset = anchor - self.viewport / 2.0;
+            camera.position += screen_to_world(offset, camera.scale, camera.rotation)
+                - screen_to_world(offset, old_scale, old_rotation);
+        }
+
+        // Touch panning, then flinging once the fingers are lifted
+        let pan = std::mem::take(&mut self.touch_pan);
+        camera.position += screen_to_world(pan, camera.scale, camera.rotation);
+        if self.fling != Vec2::ZERO {
+            let step = self.fling * time_delta;
+            camera.position += screen_to_world(step, camera.scale, camera.rotation);
+            self.fling *= (-FLING_FRICTION * time_delta).exp();
+            if self.fling.length() < MIN_FLING_SPEED {
+                self.fling = Vec2::ZERO;
+            }
+        }
 
         // Mouse dragging
         if self.mouse_state == ElementState::Pressed {
-            camera.position =
-                self.camera_pos + (self.mouse_pos - self.mouse_pos_held) / camera.scale;
+            if self.grabbed_pos.is_some() {
+                camera.position = self.camera_pos;
+            } else {
+                let drag = self.mouse_pos - self.mouse_pos_held;
+                camera.position =
+                    self.camera_pos + screen_to_world(drag, camera.scale, camera.rotation);
+            }
         }
 
         // Frame interval
         self.prev_elapsed = self.current_elapsed;
-        self.current_elapsed = self.start.elapsed().as_secs_f32();
+        self.current_elapsed = match self.fixed_step {
+            Some(step) => self.current_elapsed + step,
+            None => self.start.elapsed().as_secs_f32(),
+        };
+    }
+
+    /// Makes every frame last exactly `step` seconds, or follows the clock again with `None`.
+    pub fn set_fixed_step(&mut self, step: Option<f32>) {
+        if self.fixed_step.is_some() && step.is_none() {
+            // Carry on from the current time instead of jumping to the clock's
+            self.start = Instant::now() - Duration::from_secs_f32(self.current_elapsed);
+        }
+        self.fixed_step = step;
     }
 
     pub fn interact(&mut self, window: &Window, event: &WindowEvent, camera: &Camera) {
         match event {
             WindowEvent::CursorMoved { position, .. } => {
-                self.mouse_pos = vec2(position.x as f32, position.y as f32);
+                self.mouse_pos = vec2(position.x as f32, position.y as f32) * self.cursor_scale;
 
                 if self.mouse_state == ElementState::Pressed {
                     window.request_redraw();
@@ -65,6 +163,7 @@ impl ExampleSceneController {
             }
             WindowEvent::MouseInput { state, .. } => {
                 self.mouse_state = *state;
+                self.grabbed_pos = None;
                 if self.mouse_state == ElementState::Pressed {
                     self.mouse_pos_held = self.mouse_pos;
                     self.camera_pos = camera.position;
@@ -78,6 +177,34 @@ impl ExampleSceneController {
                 };
 
                 self.hard_scale *= 2_f32.powf(self.scroll_speed * my * 0.1);
+                self.zoom_anchor = None;
+
+                window.request_redraw();
+            }
+            WindowEvent::Touch(touch) => {
+                let pos =
+                    vec2(touch.location.x as f32, touch.location.y as f32) * self.cursor_scale;
+                match touch.phase {
+                    TouchPhase::Started => {
+                        self.touches.insert(touch.id, pos);
+                        // Catching the scene stops it
+                        self.fling = Vec2::ZERO;
+                        self.pan_velocity = Vec2::ZERO;
+                    }
+                    TouchPhase::Moved => {
+                        if let Some(prev) = self.touches.insert(touch.id, pos) {
+                            self.touch_moved(touch.id, prev, pos);
+                        }
+                    }
+                    TouchPhase::Ended | TouchPhase::Cancelled => {
+                        self.touches.remove(&touch.id);
+                        let recent = self.last_touch_move.elapsed().as_secs_f32() < FLING_WINDOW;
+                        if touch.phase == TouchPhase::Ended && self.touches.is_empty() && recent {
+                            self.fling = self.pan_velocity;
+                        }
+                        self.pan_velocity = Vec2::ZERO;
+                    }
+                }
 
                 window.request_redraw();
             }
@@ -85,7 +212,82 @@ impl ExampleSceneController {
         }
     }
 
+    /// Whether the camera is still easing towards the zoom level scrolled to, or flung.
+    pub fn is_easing(&self, camera: &Camera) -> bool {
+        let tolerance = self.hard_scale.abs().max_element() * 1e-3;
+        (self.hard_scale - camera.scale).abs().max_element() > tolerance || self.fling != Vec2::ZERO
+    }
+
+    /// Pans with one finger, or pans, pinch-zooms and rotates with two.
+    fn touch_moved(&mut self, id: u64, prev: Vec2, pos: Vec2) {
+        let now = Instant::now();
+        let dt = (now - self.last_touch_move).as_secs_f32();
+        self.last_touch_move = now;
+
+        match self.touches.len() {
+            1 => {
+                self.touch_pan += pos - prev;
+                // Smoothed, as touch events come in unevenly
+                if dt > 0.0 {
+                    self.pan_velocity = self.pan_velocity.lerp((pos - prev) / dt, 0.5);
+                }
+            }
+            2 => {
+                let Some(&other) = self
+                    .touches
+                    .iter()
+                    .find_map(|(&other_id, pos)| (other_id != id).then_some(pos))
+                else {
+                    return;
+                };
+                let (before, after) = (prev - other, pos - other);
+                if before.length() < 1.0 || after.length() < 1.0 {
+                    return;
+                }
+
+                self.hard_scale *= after.length() / before.length();
+                self.zoom_anchor = Some((pos + other) / 2.0);
+                // The center moves half as much as the finger that moved
+                self.touch_pan += (pos - prev) / 2.0;
+
+                let turn = after.y.atan2(after.x) - before.y.atan2(before.x);
+                self.touch_rotation += (turn + PI).rem_euclid(TAU) - PI;
+            }
+            _ => {}
+        }
+    }
+
+    /// Makes the time spent without rendering not count, so that nothing jumps ahead next frame.
+    pub fn resume(&mut self) {
+        if self.fixed_step.is_none() {
+            self.start = Instant::now() - Duration::from_secs_f32(self.current_elapsed);
+        }
+    }
+
+    /// Drags an object at the given position with the current press, instead of the camera.
+    pub fn grab(&mut self, object_pos: Vec2) {
+        if self.mouse_state == ElementState::Pressed {
+            self.grabbed_pos = Some(object_pos);
+        }
+    }
+
+    /// Where the grabbed object should be moved to, if any.
+    pub fn grabbed_position(&self, camera: &Camera) -> Option<Vec2> {
+        let drag = self.mouse_pos - self.mouse_pos_held;
+        self.grabbed_pos
+            .map(|pos| pos + screen_to_world(drag, camera.scale, camera.rotation))
+    }
+
+    pub fn mouse_pos(&self) -> Vec2 {
+        self.mouse_pos
+    }
+
     pub fn current_elapsed(&self) -> f32 {
         self.current_elapsed
     }
-}
\ No newline at end of file
+
+    /// Duration of the last frame, in seconds.
+    pub fn frame_delta(&self) -> f32 {
+        self.current_elapsed - self.prev_elapsed
+    }
+}
@@ -0,0 +1,78 @@
+//! Deciding when a frame is worth rendering, so that nothing is drawn while the scene is still.
+
+use web_time::{Duration, Instant};
+
+/// Lowest frame rate cap, as a frame interval has to fit in a `Duration`.
+pub const MIN_FPS_CAP: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextFrame {
+    /// Nothing changed, or nobody can see it.
+    Idle,
+    Now,
+    /// Something changed, but the frame rate cap holds the frame back.
+    After(Duration),
+}
+
+#[derive(Debug, Default)]
+pub struct RenderScheduler {
+    /// Frame rate not to exceed, if any.
+    fps_cap: Option<f32>,
+    /// Whether something changed since the last frame.
+    dirty: bool,
+    /// Whether the page or window can't be seen.
+    hidden: bool,
+    /// Whether rendering stopped since the last frame, so that time stood still meanwhile.
+    idle: bool,
+    last_frame: Option<Instant>,
+}
+
+impl RenderScheduler {
+    /// Asks for a frame showing a change.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn set_fps_cap(&mut self, fps: Option<f32>) {
+        self.fps_cap = fps;
+    }
+
+    /// Pauses rendering while hidden, and catches up once shown again.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+        if !hidden {
+            self.dirty = true;
+        }
+    }
+
+    /// When the next frame should be rendered.
+    pub fn next_frame(&mut self, now: Instant) -> NextFrame {
+        if self.hidden || !self.dirty {
+            self.idle = true;
+            return NextFrame::Idle;
+        }
+
+        match self.wait(now) {
+            Some(delay) => NextFrame::After(delay),
+            None => NextFrame::Now,
+        }
+    }
+
+    /// Whether a frame may be rendered right away.
+    pub fn is_due(&self, now: Instant) -> bool {
+        !self.hidden && self.wait(now).is_none()
+    }
+
+    /// Records a rendered frame, returning whether it is the first one after being idle.
+    pub fn rendered(&mut self, now: Instant) -> bool {
+        self.dirty = false;
+        self.last_frame = Some(now);
+        std::mem::take(&mut self.idle)
+    }
+
+    /// How long the frame rate cap holds the next frame back.
+    fn wait(&self, now: Instant) -> Option<Duration> {
+        let due = self.last_frame? + Duration::from_secs_f32(1.0 / self.fps_cap?);
+        (due > now).then(|| due - now)
+    }
+}
@@ -0,0 +1,267 @@
+//! Keeping a connection to a tracker up, whatever carries its messages.
+
+use std::collections::HashMap;
+
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+use log::{debug, info, warn};
+use web_time::{Duration, Instant};
+
+use super::{TrackingMessage, TrackingReceiver};
+
+/// Wait before reconnecting the first time, doubled after every failed attempt.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    Connecting,
+    Open,
+    /// Lost or refused, reconnecting after a delay.
+    Closed,
+}
+
+impl ConnectionStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Connecting => "connecting",
+            Self::Open => "open",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+/// Something that happened on a connection, as reported by its transport.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportEvent {
+    Opened,
+    Binary(Vec<u8>),
+    Text(String),
+    Closed,
+}
+
+/// Carries messages from a tracker, e.g. over a WebSocket.
+pub trait TrackingTransport {
+    /// Starts connecting to `url`, dropping any previous connection.
+    fn connect(&mut self, url: &str) -> anyhow::Result<()>;
+
+    /// Takes what happened since the last call, oldest first.
+    fn take_events(&mut self) -> Vec<TransportEvent>;
+}
+
+/// A connection to a tracker feeding a [`TrackingReceiver`], reconnecting whenever it is lost.
+pub struct TrackingConnection<T> {
+    transport: T,
+    url: String,
+    status: ConnectionStatus,
+    receiver: TrackingReceiver,
+    /// When to connect again, while closed.
+    retry_at: Option<Instant>,
+    retry_delay: Duration,
+}
+
+impl<T: TrackingTransport> TrackingConnection<T> {
+    pub fn new(mut transport: T, url: &str, receiver: TrackingReceiver) -> anyhow::Result<Self> {
+        transport.connect(url)?;
+        info!("connecting to tracker at {url}");
+
+        Ok(Self {
+            transport,
+            url: url.to_owned(),
+            status: ConnectionStatus::Connecting,
+            receiver,
+            retry_at: None,
+            retry_delay: RECONNECT_DELAY,
+        })
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.status
+    }
+
+    pub fn receiver(&self) -> &TrackingReceiver {
+        &self.receiver
+    }
+
+    /// Handles what the transport reported since the last update, and reconnects once due.
+    pub fn update(&mut self, now: Instant) {
+        for event in self.transport.take_events() {
+            let res = match &event {
+                TransportEvent::Opened => {
+                    info!("connected to tracker at {}", self.url);
+                    self.status = ConnectionStatus::Open;
+                    self.retry_delay = RECONNECT_DELAY;
+                    Ok(())
+                }
+                TransportEvent::Binary(data) => {
+                    self.receiver.receive(TrackingMessage::Binary(data))
+                }
+                TransportEvent::Text(text) => self.receiver.receive(TrackingMessage::Text(text)),
+                TransportEvent::Closed => {
+                    warn!(
+                        "tracker connection to {} closed, reconnecting in {:?}",
+                        self.url, self.retry_delay
+                    );
+                    self.status = ConnectionStatus::Closed;
+                    self.schedule_retry(now);
+                    Ok(())
+                }
+            };
+            if let Err(e) = res {
+                debug!("invalid tracking packet: {e}");
+            }
+        }
+
+        if self.retry_at.is_some_and(|at| now >= at) {
+            self.retry_at = None;
+            match self.transport.connect(&self.url) {
+                Ok(()) => self.status = ConnectionStatus::Connecting,
+                Err(e) => {
+                    warn!("couldn't reconnect to tracker at {}: {e}", self.url);
+                    self.schedule_retry(now);
+                }
+            }
+        }
+    }
+
+    /// Uses the current head pose as the neutral one.
+    pub fn calibrate(&mut self) {
+        self.receiver.calibrate();
+    }
+
+    /// Smooths the latest values over `dt` seconds, then writes them through the mapping.
+    pub fn write(&mut self, puppet: &Puppet, values: &mut HashMap<String, Vec2>, dt: f32) {
+        self.receiver.write(puppet, values, dt);
+    }
+
+    fn schedule_retry(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.retry_delay);
+        self.retry_delay = (self.retry_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use anyhow::anyhow;
+
+    use super::super::{Protocol, TrackingMapping};
+    use super::*;
+
+    /// Stands in for a tracker, shared between the test and the connection it is handed to.
+    #[derive(Clone, Default)]
+    struct MockTransport {
+        events: Rc<RefCell<Vec<TransportEvent>>>,
+        connects: Rc<RefCell<Vec<String>>>,
+        refuse: Rc<RefCell<bool>>,
+    }
+
+    impl MockTransport {
+        fn send(&self, event: TransportEvent) {
+            self.events.borrow_mut().push(event);
+        }
+
+        fn connects(&self) -> usize {
+            self.connects.borrow().len()
+        }
+    }
+
+    impl TrackingTransport for MockTransport {
+        fn connect(&mut self, url: &str) -> anyhow::Result<()> {
+            if *self.refuse.borrow() {
+                return Err(anyhow!("refused"));
+            }
+            self.connects.borrow_mut().push(url.to_owned());
+            Ok(())
+        }
+
+        fn take_events(&mut self) -> Vec<TransportEvent> {
+            std::mem::take(&mut self.events.borrow_mut())
+        }
+    }
+
+    const URL: &str = "ws://localhost:39540";
+
+    fn connect(mock: &MockTransport) -> TrackingConnection<MockTransport> {
+        let receiver = TrackingReceiver::new(Protocol::VTubeStudio, TrackingMapping::default());
+        TrackingConnection::new(mock.clone(), URL, receiver).unwrap()
+    }
+
+    fn secs(s: f32) -> Duration {
+        Duration::from_secs_f32(s)
+    }
+
+    #[test]
+    fn receives_messages_once_open() {
+        let mock = MockTransport::default();
+        let mut connection = connect(&mock);
+        assert_eq!(*mock.connects.borrow(), [URL]);
+        assert_eq!(connection.status(), ConnectionStatus::Connecting);
+
+        mock.send(TransportEvent::Opened);
+        mock.send(TransportEvent::Text("not json".to_owned()));
+        mock.send(TransportEvent::Text(
+            r#"{"BlendShapes": [{"k": "JawOpen", "v": 0.5}]}"#.to_owned(),
+        ));
+        connection.update(Instant::now());
+
+        // Invalid messages are skipped without dropping the connection
+        assert_eq!(connection.status(), ConnectionStatus::Open);
+        assert_eq!(connection.receiver().frame().values["JawOpen"], 0.5);
+    }
+
+    #[test]
+    fn reconnects_with_growing_delays() {
+        let mock = MockTransport::default();
+        let mut connection = connect(&mock);
+        let start = Instant::now();
+
+        mock.send(TransportEvent::Opened);
+        mock.send(TransportEvent::Closed);
+        connection.update(start);
+        assert_eq!(connection.status(), ConnectionStatus::Closed);
+
+        connection.update(start + secs(0.5));
+        assert_eq!(mock.connects(), 1);
+        connection.update(start + secs(1.0));
+        assert_eq!(mock.connects(), 2);
+        assert_eq!(connection.status(), ConnectionStatus::Connecting);
+
+        // Refused again, so the next attempt waits twice as long
+        mock.send(TransportEvent::Closed);
+        connection.update(start + secs(1.0));
+        connection.update(start + secs(2.5));
+        assert_eq!(mock.connects(), 2);
+        connection.update(start + secs(3.0));
+        assert_eq!(mock.connects(), 3);
+
+        // Connecting successfully starts over from the shortest delay
+        mock.send(TransportEvent::Opened);
+        mock.send(TransportEvent::Closed);
+        connection.update(start + secs(3.0));
+        connection.update(start + secs(4.0));
+        assert_eq!(mock.connects(), 4);
+    }
+
+    #[test]
+    fn retries_when_connecting_fails() {
+        let mock = MockTransport::default();
+        let mut connection = connect(&mock);
+        let start = Instant::now();
+
+        mock.send(TransportEvent::Closed);
+        *mock.refuse.borrow_mut() = true;
+        connection.update(start);
+        connection.update(start + secs(1.0));
+        assert_eq!(connection.status(), ConnectionStatus::Closed);
+
+        *mock.refuse.borrow_mut() = false;
+        connection.update(start + secs(2.5));
+        assert_eq!(mock.connects(), 1);
+        connection.update(start + secs(3.0));
+        assert_eq!(mock.connects(), 2);
+        assert_eq!(connection.status(), ConnectionStatus::Connecting);
+    }
+}
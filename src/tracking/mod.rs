@@ -0,0 +1,365 @@
+//! Face tracking received over a WebSocket, mapped onto puppet parameters.
+//!
+//! Browsers can't receive UDP, so trackers that send UDP packets
+//! need a relay forwarding each packet as one WebSocket message.
+//!
+//! Messages go through a [`TrackingReceiver`], which doesn't need a socket, and connections
+//! through a [`TrackingTransport`], so that both decoding and reconnecting can be driven from
+//! tests.
+
+// Only the web build connects to trackers, decoding builds natively for the tests
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
+mod connection;
+mod openseeface;
+mod osc;
+#[cfg(target_arch = "wasm32")]
+mod socket;
+mod vmc;
+mod vtube_studio;
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context};
+use glam::Vec2;
+use inox2d::puppet::Puppet;
+use log::info;
+
+use crate::params::{set_axis, ParamAxis};
+
+use self::vmc::VmcDecoder;
+
+pub use self::connection::{
+    ConnectionStatus, TrackingConnection, TrackingTransport, TransportEvent,
+};
+#[cfg(target_arch = "wasm32")]
+pub use self::socket::{TrackingSocket, WebSocketTransport};
+
+/// Default mapping, from ARKit-style blendshapes to the parameters of the Inochi2D example puppets.
+pub const DEFAULT_MAPPING: &str = r#"{
+    "smoothing": 0.05,
+    "bindings": [
+        { "input": "HeadYaw", "param": "Head:: Yaw-Pitch", "axis": "x", "in": [-30, 30], "out": [-1, 1], "deadzone": 2 },
+        { "input": "HeadPitch", "param": "Head:: Yaw-Pitch", "axis": "y", "in": [-30, 30], "out": [-1, 1], "deadzone": 2 },
+        { "input": "HeadRoll", "param": "Head:: Roll", "axis": "x", "in": [-30, 30], "out": [-1, 1], "deadzone": 2 },
+        { "input": "EyeBlinkLeft", "param": "Eye:: Left:: Blink", "axis": "x", "in": [0, 1], "out": [0, 1] },
+        { "input": "EyeBlinkRight", "param": "Eye:: Right:: Blink", "axis": "x", "in": [0, 1], "out": [0, 1] },
+        { "input": "JawOpen", "param": "Mouth:: Open", "axis": "x", "in": [0, 1], "out": [0, 1] }
+    ]
+}"#;
+
+/// The latest state reported by a tracker, as named values.
+///
+/// Blendshapes keep the tracker's names, and every decoder also provides `EyeBlinkLeft`,
+/// `EyeBlinkRight` and `JawOpen`, e.g. from VRM presets for VMC. Head pose is stored as
+/// `HeadYaw`, `HeadPitch`, `HeadRoll` (in degrees) and `HeadX`, `HeadY`, `HeadZ`.
+#[derive(Debug, Clone, Default)]
+pub struct TrackingFrame {
+    pub values: HashMap<String, f32>,
+}
+
+/// Values captured by calibration, as the user's neutral pose.
+const HEAD_POSE: [&str; 6] = [
+    "HeadYaw",
+    "HeadPitch",
+    "HeadRoll",
+    "HeadX",
+    "HeadY",
+    "HeadZ",
+];
+
+impl TrackingFrame {
+    pub fn set_head_rotation(&mut self, yaw: f32, pitch: f32, roll: f32) {
+        self.values.insert("HeadYaw".to_owned(), yaw);
+        self.values.insert("HeadPitch".to_owned(), pitch);
+        self.values.insert("HeadRoll".to_owned(), roll);
+    }
+
+    pub fn set_head_position(&mut self, x: f32, y: f32, z: f32) {
+        self.values.insert("HeadX".to_owned(), x);
+        self.values.insert("HeadY".to_owned(), y);
+        self.values.insert("HeadZ".to_owned(), z);
+    }
+}
+
+/// Drives one axis of a parameter from one tracking value.
+pub struct TrackingBinding {
+    pub input: String,
+    pub param: String,
+    pub axis: ParamAxis,
+    /// Tracking values mapped to the ends of `output`. Values outside are clamped.
+    pub input_range: (f32, f32),
+    pub output_range: (f32, f32),
+    /// Inputs closer than this to 0 are treated as 0, hiding tracking jitter around the neutral pose.
+    pub deadzone: f32,
+}
+
+pub struct TrackingMapping {
+    pub bindings: Vec<TrackingBinding>,
+    /// Time constant of the exponential smoothing applied to inputs, in seconds.
+    pub smoothing: f32,
+}
+
+impl TrackingMapping {
+    /// Parses a mapping, see [`DEFAULT_MAPPING`] for the format.
+    pub fn from_json(src: &str) -> anyhow::Result<Self> {
+        let root = json::parse(src)?;
+
+        let bindings = root["bindings"]
+            .members()
+            .map(|binding| {
+                let input = binding["input"]
+                    .as_str()
+                    .context("binding without an input")?
+                    .to_owned();
+                let param = binding["param"]
+                    .as_str()
+                    .with_context(|| format!("binding for {input:?} without a param"))?
+                    .to_owned();
+                let axis = ParamAxis::parse(binding["axis"].as_str().unwrap_or("x"))?;
+                let range = |key: &str| match (binding[key][0].as_f32(), binding[key][1].as_f32()) {
+                    (Some(a), Some(b)) => Ok((a, b)),
+                    (None, None) => Ok((0.0, 1.0)),
+                    _ => Err(anyhow!("invalid {key:?} range for {input:?}")),
+                };
+
+                Ok(TrackingBinding {
+                    input_range: range("in")?,
+                    output_range: range("out")?,
+                    deadzone: binding["deadzone"].as_f32().unwrap_or(0.0).abs(),
+                    input,
+                    param,
+                    axis,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            bindings,
+            smoothing: root["smoothing"].as_f32().unwrap_or(0.0),
+        })
+    }
+
+    pub fn write(
+        &self,
+        frame: &TrackingFrame,
+        puppet: &Puppet,
+        values: &mut HashMap<String, Vec2>,
+    ) {
+        for binding in &self.bindings {
+            let Some(&input) = frame.values.get(&binding.input) else {
+                continue;
+            };
+            // Shrink rather than cut, so that leaving the dead zone doesn't jump
+            let input = input.signum() * (input.abs() - binding.deadzone).max(0.0);
+
+            let (in_a, in_b) = binding.input_range;
+            let (out_a, out_b) = binding.output_range;
+            let t = if in_a == in_b {
+                0.0
+            } else {
+                ((input - in_a) / (in_b - in_a)).clamp(0.0, 1.0)
+            };
+
+            let value = out_a + t * (out_b - out_a);
+            set_axis(puppet, values, &binding.param, binding.axis, value);
+        }
+    }
+}
+
+impl Default for TrackingMapping {
+    fn default() -> Self {
+        Self::from_json(DEFAULT_MAPPING).expect("default tracking mapping is valid")
+    }
+}
+
+/// Wire formats understood by [`TrackingSocket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// VMC protocol over OSC, in binary messages.
+    Vmc,
+    /// OpenSeeFace packets, in binary messages.
+    OpenSeeFace,
+    /// The VTube Studio iPhone app's JSON, in text or binary messages.
+    VTubeStudio,
+}
+
+impl Protocol {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "vmc" => Ok(Self::Vmc),
+            "openseeface" => Ok(Self::OpenSeeFace),
+            "vtubestudio" => Ok(Self::VTubeStudio),
+            _ => Err(anyhow!("unknown tracking protocol {s:?}")),
+        }
+    }
+}
+
+enum Decoder {
+    Vmc(VmcDecoder),
+    OpenSeeFace,
+    VTubeStudio,
+}
+
+impl Decoder {
+    fn new(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Vmc => Self::Vmc(VmcDecoder::default()),
+            Protocol::OpenSeeFace => Self::OpenSeeFace,
+            Protocol::VTubeStudio => Self::VTubeStudio,
+        }
+    }
+
+    fn binary(&mut self, data: &[u8], frame: &mut TrackingFrame) -> anyhow::Result<()> {
+        match self {
+            Self::Vmc(vmc) => {
+                for message in osc::decode(data)? {
+                    vmc.handle(&message, frame);
+                }
+                Ok(())
+            }
+            Self::OpenSeeFace => openseeface::decode(data, frame),
+            // Relays may forward the UDP payload as is
+            Self::VTubeStudio => vtube_studio::decode(std::str::from_utf8(data)?, frame),
+        }
+    }
+
+    fn text(&mut self, data: &str, frame: &mut TrackingFrame) -> anyhow::Result<()> {
+        match self {
+            Self::VTubeStudio => vtube_studio::decode(data, frame),
+            _ => Err(anyhow!("unexpected text message")),
+        }
+    }
+}
+
+/// A message as received from a tracker or relay.
+#[derive(Debug, Clone, Copy)]
+pub enum TrackingMessage<'a> {
+    Binary(&'a [u8]),
+    Text(&'a str),
+}
+
+/// Decodes tracking messages and turns them into parameter values, wherever they come from.
+pub struct TrackingReceiver {
+    decoder: Decoder,
+    /// The latest values received, as is.
+    frame: TrackingFrame,
+    /// Values after calibration and smoothing.
+    filtered: TrackingFrame,
+    /// Neutral head pose, subtracted from received values.
+    offsets: HashMap<String, f32>,
+    pub mapping: TrackingMapping,
+}
+
+impl TrackingReceiver {
+    pub fn new(protocol: Protocol, mapping: TrackingMapping) -> Self {
+        Self {
+            decoder: Decoder::new(protocol),
+            frame: TrackingFrame::default(),
+            filtered: TrackingFrame::default(),
+            offsets: HashMap::new(),
+            mapping,
+        }
+    }
+
+    /// Decodes a message into the latest values.
+    pub fn receive(&mut self, message: TrackingMessage) -> anyhow::Result<()> {
+        match message {
+            TrackingMessage::Binary(data) => self.decoder.binary(data, &mut self.frame),
+            TrackingMessage::Text(data) => self.decoder.text(data, &mut self.frame),
+        }
+    }
+
+    /// The latest values received, before calibration and smoothing.
+    pub fn frame(&self) -> &TrackingFrame {
+        &self.frame
+    }
+
+    /// Uses the current head pose as the neutral one.
+    pub fn calibrate(&mut self) {
+        for key in HEAD_POSE {
+            if let Some(&value) = self.frame.values.get(key) {
+                self.offsets.insert(key.to_owned(), value);
+            }
+        }
+        info!("tracker calibrated: {:?}", self.offsets);
+    }
+
+    /// Smooths the latest values over `dt` seconds, then writes them through the mapping.
+    pub fn write(&mut self, puppet: &Puppet, values: &mut HashMap<String, Vec2>, dt: f32) {
+        let smoothing = self.mapping.smoothing;
+        let t = if smoothing > 0.0 {
+            1.0 - (-dt / smoothing).exp()
+        } else {
+            1.0
+        };
+
+        for (name, &raw) in &self.frame.values {
+            let target = raw - self.offsets.get(name).copied().unwrap_or(0.0);
+            let value = self.filtered.values.entry(name.clone()).or_insert(target);
+            *value += (target - *value) * t;
+        }
+
+        self.mapping.write(&self.filtered, puppet, values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::osc::{self, OscArg, OscMessage};
+    use super::*;
+
+    fn blend(name: &str, value: f32) -> Vec<u8> {
+        osc::encode(&OscMessage {
+            address: "/VMC/Ext/Blend/Val".to_owned(),
+            args: vec![OscArg::String(name.to_owned()), OscArg::Float(value)],
+        })
+    }
+
+    fn apply() -> Vec<u8> {
+        osc::encode(&OscMessage {
+            address: "/VMC/Ext/Blend/Apply".to_owned(),
+            args: vec![],
+        })
+    }
+
+    #[test]
+    fn receives_vmc_messages_across_packets() {
+        let mut receiver = TrackingReceiver::new(Protocol::Vmc, TrackingMapping::default());
+
+        // Senders may split a batch over several packets
+        let packet = osc::encode_bundle(&[blend("JawOpen", 0.75)]);
+        receiver.receive(TrackingMessage::Binary(&packet)).unwrap();
+        assert!(receiver.frame().values.is_empty());
+
+        let packet = osc::encode_bundle(&[blend("EyeBlinkLeft", 0.5), apply()]);
+        receiver.receive(TrackingMessage::Binary(&packet)).unwrap();
+        assert_eq!(receiver.frame().values["JawOpen"], 0.75);
+        assert_eq!(receiver.frame().values["EyeBlinkLeft"], 0.5);
+    }
+
+    #[test]
+    fn rejects_invalid_messages() {
+        let mut receiver = TrackingReceiver::new(Protocol::Vmc, TrackingMapping::default());
+        let packet = osc::encode_bundle(&[blend("JawOpen", 1.0), apply()]);
+
+        assert!(receiver
+            .receive(TrackingMessage::Binary(&packet[..packet.len() - 1]))
+            .is_err());
+        assert!(receiver.receive(TrackingMessage::Text("{}")).is_err());
+        assert!(receiver.frame().values.is_empty());
+    }
+
+    #[test]
+    fn calibration_uses_the_latest_head_pose() {
+        let mut receiver = TrackingReceiver::new(Protocol::VTubeStudio, TrackingMapping::default());
+        let message = r#"{"FaceFound": true, "Rotation": {"x": 5, "y": -10, "z": 2},
+            "BlendShapes": [{"k": "JawOpen", "v": 0.5}]}"#;
+        receiver.receive(TrackingMessage::Text(message)).unwrap();
+        receiver.calibrate();
+
+        assert_eq!(receiver.offsets["HeadYaw"], -10.0);
+        assert_eq!(receiver.offsets["HeadPitch"], 5.0);
+        assert!(!receiver.offsets.contains_key("JawOpen"));
+    }
+}
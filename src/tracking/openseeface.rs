@@ -0,0 +1,185 @@
+//! OpenSeeFace's binary UDP packets, forwarded as WebSocket messages by a relay.
+
+use anyhow::anyhow;
+
+use super::TrackingFrame;
+
+/// Size of the data sent for each tracked face.
+const FACE_SIZE: usize = 1785;
+/// Number of 2D landmarks and 3D points that follow the head pose.
+const LANDMARKS: usize = 68;
+const POINTS: usize = 70;
+
+/// Names of the facial features at the end of each face, in packet order.
+const FEATURES: [&str; 14] = [
+    "EyeLeft",
+    "EyeRight",
+    "EyebrowSteepnessLeft",
+    "EyebrowUpDownLeft",
+    "EyebrowQuirkLeft",
+    "EyebrowSteepnessRight",
+    "EyebrowUpDownRight",
+    "EyebrowQuirkRight",
+    "MouthCornerUpDownLeft",
+    "MouthCornerInOutLeft",
+    "MouthCornerUpDownRight",
+    "MouthCornerInOutRight",
+    "MouthOpen",
+    "MouthWide",
+];
+
+/// Decodes the first face of a packet. Packets may hold several faces back to back.
+pub fn decode(packet: &[u8], frame: &mut TrackingFrame) -> anyhow::Result<()> {
+    if packet.len() < FACE_SIZE || packet.len() % FACE_SIZE != 0 {
+        return Err(anyhow!("invalid OpenSeeFace packet size {}", packet.len()));
+    }
+
+    let mut reader = Reader { data: packet };
+    reader.skip(8 + 4 + 8); // timestamp, face id, camera resolution
+
+    let blink_right = reader.f32();
+    let blink_left = reader.f32();
+    let success = reader.u8() != 0;
+    if !success {
+        return Ok(());
+    }
+    reader.skip(4 + 16); // PnP error, quaternion
+
+    // Euler angles are in degrees, with pitch offset by the camera setup,
+    // so they are meant to be calibrated
+    let pitch = reader.f32();
+    let yaw = reader.f32();
+    let roll = reader.f32();
+    frame.set_head_rotation(yaw, pitch, roll);
+
+    let (x, y, z) = (reader.f32(), reader.f32(), reader.f32());
+    frame.set_head_position(x, y, z);
+
+    // Landmark confidences and positions, then 3D points
+    reader.skip(LANDMARKS * 4 + LANDMARKS * 8 + POINTS * 12);
+
+    for feature in FEATURES {
+        frame.values.insert(feature.to_owned(), reader.f32());
+    }
+
+    // Shared names, so that the same mapping works across trackers.
+    // OpenSeeFace reports how open the eyes are rather than how closed.
+    frame
+        .values
+        .insert("EyeBlinkLeft".to_owned(), 1.0 - blink_left.clamp(0.0, 1.0));
+    frame.values.insert(
+        "EyeBlinkRight".to_owned(),
+        1.0 - blink_right.clamp(0.0, 1.0),
+    );
+    frame
+        .values
+        .insert("JawOpen".to_owned(), frame.values["MouthOpen"]);
+
+    Ok(())
+}
+
+/// Little-endian reads, the size having been checked beforehand.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn skip(&mut self, len: usize) {
+        self.data = &self.data[len..];
+    }
+
+    fn u8(&mut self) -> u8 {
+        let b = self.data[0];
+        self.skip(1);
+        b
+    }
+
+    fn f32(&mut self) -> f32 {
+        let f = f32::from_le_bytes(self.data[..4].try_into().unwrap());
+        self.skip(4);
+        f
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a face the way OpenSeeFace lays it out, with recognizable values.
+    fn face(success: bool) -> Vec<u8> {
+        let mut face = Vec::with_capacity(FACE_SIZE);
+        let f32s = |face: &mut Vec<u8>, values: &[f32]| {
+            for v in values {
+                face.extend_from_slice(&v.to_le_bytes());
+            }
+        };
+
+        face.extend_from_slice(&12.5f64.to_le_bytes()); // timestamp
+        face.extend_from_slice(&0i32.to_le_bytes()); // face id
+        f32s(&mut face, &[640.0, 480.0]); // camera resolution
+        f32s(&mut face, &[0.75, 0.25]); // right and left eye openness
+        face.push(success as u8);
+        f32s(&mut face, &[0.0; 5]); // PnP error, quaternion
+        f32s(&mut face, &[10.0, -20.0, 5.0]); // pitch, yaw, roll
+        f32s(&mut face, &[1.0, 2.0, 3.0]); // translation
+        f32s(&mut face, &vec![0.5; LANDMARKS * 3 + POINTS * 3]);
+        let features: Vec<f32> = (0..FEATURES.len()).map(|i| i as f32 / 100.0).collect();
+        f32s(&mut face, &features);
+
+        assert_eq!(face.len(), FACE_SIZE);
+        face
+    }
+
+    #[test]
+    fn decodes_a_face() {
+        let mut frame = TrackingFrame::default();
+        decode(&face(true), &mut frame).unwrap();
+
+        let values = &frame.values;
+        assert_eq!(values["HeadYaw"], -20.0);
+        assert_eq!(values["HeadPitch"], 10.0);
+        assert_eq!(values["HeadRoll"], 5.0);
+        assert_eq!(values["HeadZ"], 3.0);
+        assert_eq!(values["EyeLeft"], 0.0);
+        assert_eq!(values["MouthWide"], 0.13);
+        assert_eq!(values["EyeBlinkLeft"], 0.75);
+        assert_eq!(values["EyeBlinkRight"], 0.25);
+        assert_eq!(values["JawOpen"], values["MouthOpen"]);
+    }
+
+    #[test]
+    fn decodes_the_first_of_several_faces() {
+        let mut packet = face(true);
+        packet.extend(face(false));
+
+        let mut frame = TrackingFrame::default();
+        decode(&packet, &mut frame).unwrap();
+        assert_eq!(frame.values["HeadYaw"], -20.0);
+    }
+
+    #[test]
+    fn lost_faces_keep_the_last_values() {
+        let mut frame = TrackingFrame::default();
+        decode(&face(false), &mut frame).unwrap();
+        assert!(frame.values.is_empty());
+    }
+
+    #[test]
+    fn rejects_packets_of_the_wrong_size() {
+        let face = face(true);
+        for len in [0, 1, FACE_SIZE - 1, FACE_SIZE + 1, FACE_SIZE * 2 - 4] {
+            let packet: Vec<u8> = face.iter().copied().cycle().take(len).collect();
+            let mut frame = TrackingFrame::default();
+            assert!(decode(&packet, &mut frame).is_err(), "{len} bytes decoded");
+            assert!(frame.values.is_empty());
+        }
+    }
+
+    #[test]
+    fn never_panics_on_garbage() {
+        for byte in [0x00, 0x7f, 0xff] {
+            let mut frame = TrackingFrame::default();
+            let _ = decode(&[byte; FACE_SIZE], &mut frame);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! A minimal decoder for OSC 1.0 packets.
+
+use anyhow::{anyhow, Context};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Blob(Vec<u8>),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    Nil,
+}
+
+impl OscArg {
+    /// Numeric arguments as a float.
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            Self::Int(i) => Some(i as f32),
+            Self::Float(f) => Some(f),
+            Self::Long(l) => Some(l as f32),
+            Self::Double(d) => Some(d as f32),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Decodes a packet, flattening bundles into their messages.
+pub fn decode(packet: &[u8]) -> anyhow::Result<Vec<OscMessage>> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<OscMessage>) -> anyhow::Result<()> {
+    let mut reader = Reader { data: packet };
+
+    if packet.starts_with(b"#bundle\0") {
+        reader.string()?;
+        // Time tag, messages are applied as soon as they arrive
+        reader.take(8)?;
+
+        while !reader.data.is_empty() {
+            let size = reader.i32()?;
+            let size = usize::try_from(size).context("negative OSC bundle element size")?;
+            decode_into(reader.take(size)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(anyhow!("invalid OSC address {address:?}"));
+    }
+
+    // Very old implementations may omit the type tag string
+    let tags = if reader.data.is_empty() {
+        String::new()
+    } else {
+        reader.string()?
+    };
+    let tags = tags.strip_prefix(',').unwrap_or(&tags);
+
+    let args = tags
+        .chars()
+        .map(|tag| {
+            Ok(match tag {
+                'i' => OscArg::Int(reader.i32()?),
+                'f' => OscArg::Float(f32::from_bits(reader.i32()? as u32)),
+                's' | 'S' => OscArg::String(reader.string()?),
+                'b' => {
+                    let size = usize::try_from(reader.i32()?).context("negative OSC blob size")?;
+                    let blob = reader.take(size)?.to_vec();
+                    reader.take(padding(size))?;
+                    OscArg::Blob(blob)
+                }
+                'h' => OscArg::Long(reader.i64()?),
+                'd' => OscArg::Double(f64::from_bits(reader.i64()? as u64)),
+                'T' => OscArg::Bool(true),
+                'F' => OscArg::Bool(false),
+                'N' | 'I' => OscArg::Nil,
+                _ => return Err(anyhow!("unsupported OSC type tag {tag:?}")),
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    messages.push(OscMessage { address, args });
+    Ok(())
+}
+
+/// Bytes needed to pad `len` to a multiple of 4.
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(anyhow!("truncated OSC packet"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn i32(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> anyhow::Result<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    /// A null-terminated string, padded to 4 bytes.
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .context("unterminated OSC string")?;
+        let s = std::str::from_utf8(self.take(len)?)?.to_owned();
+        // The terminator counts towards the padding
+        self.take(1 + padding(len + 1))?;
+        Ok(s)
+    }
+}
+
+/// Encodes a message, for tests to build packets with.
+#[cfg(test)]
+pub fn encode(message: &OscMessage) -> Vec<u8> {
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend_from_slice(s.as_bytes());
+        out.resize(out.len() + 1 + padding(s.len() + 1), 0);
+    }
+
+    let mut out = Vec::new();
+    string(&mut out, &message.address);
+    let tags: String = message
+        .args
+        .iter()
+        .map(|arg| match arg {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::String(_) => 's',
+            OscArg::Blob(_) => 'b',
+            OscArg::Long(_) => 'h',
+            OscArg::Double(_) => 'd',
+            OscArg::Bool(true) => 'T',
+            OscArg::Bool(false) => 'F',
+            OscArg::Nil => 'N',
+        })
+        .collect();
+    string(&mut out, &format!(",{tags}"));
+
+    for arg in &message.args {
+        match arg {
+            OscArg::Int(i) => out.extend_from_slice(&i.to_be_bytes()),
+            OscArg::Float(f) => out.extend_from_slice(&f.to_be_bytes()),
+            OscArg::String(s) => string(&mut out, s),
+            OscArg::Blob(blob) => {
+                out.extend_from_slice(&(blob.len() as i32).to_be_bytes());
+                out.extend_from_slice(blob);
+                out.resize(out.len() + padding(blob.len()), 0);
+            }
+            OscArg::Long(l) => out.extend_from_slice(&l.to_be_bytes()),
+            OscArg::Double(d) => out.extend_from_slice(&d.to_be_bytes()),
+            OscArg::Bool(_) | OscArg::Nil => {}
+        }
+    }
+    out
+}
+
+/// Wraps encoded packets in a bundle.
+#[cfg(test)]
+pub fn encode_bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = b"#bundle\0".to_vec();
+    out.extend_from_slice(&1u64.to_be_bytes());
+    for element in elements {
+        out.extend_from_slice(&(element.len() as i32).to_be_bytes());
+        out.extend_from_slice(element);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(address: &str, args: Vec<OscArg>) -> OscMessage {
+        OscMessage {
+            address: address.to_owned(),
+            args,
+        }
+    }
+
+    fn every_arg() -> OscMessage {
+        message(
+            "/every/arg",
+            vec![
+                OscArg::Int(-7),
+                OscArg::Float(0.5),
+                OscArg::String("abc".to_owned()),
+                OscArg::String("abcd".to_owned()),
+                OscArg::Blob(vec![1, 2, 3, 4, 5]),
+                OscArg::Long(1 << 40),
+                OscArg::Double(-2.25),
+                OscArg::Bool(true),
+                OscArg::Bool(false),
+                OscArg::Nil,
+            ],
+        )
+    }
+
+    #[test]
+    fn decodes_every_argument_type() {
+        let msg = every_arg();
+        assert_eq!(decode(&encode(&msg)).unwrap(), vec![msg]);
+    }
+
+    #[test]
+    fn strings_are_padded_to_four_bytes() {
+        // "/abc" fills 4 bytes, so its terminator takes 4 more
+        let packet = encode(&message("/abc", vec![OscArg::Int(1)]));
+        assert_eq!(&packet[..8], b"/abc\0\0\0\0");
+        assert_eq!(&packet[8..12], b",i\0\0");
+        assert_eq!(packet.len(), 16);
+
+        let packet = encode(&message("/ab", vec![]));
+        assert_eq!(&packet[..4], b"/ab\0");
+        assert_eq!(decode(&packet).unwrap()[0].address, "/ab");
+    }
+
+    #[test]
+    fn missing_type_tags_mean_no_arguments() {
+        let msgs = decode(b"/old\0\0\0\0").unwrap();
+        assert_eq!(msgs, vec![message("/old", vec![])]);
+    }
+
+    #[test]
+    fn flattens_nested_bundles_in_order() {
+        let a = message("/a", vec![OscArg::Int(1)]);
+        let b = message("/b", vec![OscArg::Float(2.0)]);
+        let c = message("/c", vec![]);
+        let inner = encode_bundle(&[encode(&b), encode(&c)]);
+        let packet = encode_bundle(&[encode(&a), inner]);
+
+        assert_eq!(decode(&packet).unwrap(), vec![a, b, c]);
+        assert_eq!(decode(&encode_bundle(&[])).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn truncated_packets_are_errors() {
+        let packets = [encode(&every_arg()), encode_bundle(&[encode(&every_arg())])];
+        for packet in packets {
+            // Cutting anywhere after the type tags or inside a bundle's element leaves some missing
+            let min = if packet.starts_with(b"#bundle") {
+                17
+            } else {
+                24
+            };
+            for len in min..packet.len() {
+                assert!(decode(&packet[..len]).is_err(), "{len} bytes decoded");
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_packets_are_errors() {
+        let malformed: [&[u8]; 8] = [
+            b"",
+            b"/unterminated",
+            b"no/slash\0\0\0\0,\0\0\0",
+            b"/a\0\0,x\0\0",
+            b"/a\0\0,i\0\0\0\0",
+            b"/a\0\0,b\0\0\xff\xff\xff\xff",
+            b"/a\0\0,s\0\0\xff\xfe\0\0",
+            b"#bundle\0\0\0\0\0\0\0\0\x01\xff\xff\xff\xf0",
+        ];
+        for packet in malformed {
+            assert!(decode(packet).is_err(), "{packet:?} decoded");
+        }
+    }
+
+    #[test]
+    fn never_panics_on_garbage() {
+        // A small deterministic generator, to stay free of dependencies
+        let mut state = 0x2545_f491_u32;
+        for len in 0..200 {
+            let mut packet: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect();
+            let _ = decode(&packet);
+
+            // Also with valid looking headers, to get past the first checks
+            let mut prefixed = b"#bundle\0\0\0\0\0\0\0\0\0".to_vec();
+            prefixed.append(&mut packet.clone());
+            let _ = decode(&prefixed);
+            packet.splice(0..0, b"/a\0\0,ifsbhd\0".iter().copied());
+            let _ = decode(&packet);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! The WebSocket transport carrying tracker messages.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use js_sys::{ArrayBuffer, Uint8Array};
+use log::debug;
+use wasm_bindgen::prelude::*;
+use web_sys::{BinaryType, MessageEvent, WebSocket};
+use winit::event_loop::EventLoopProxy;
+
+use crate::loader::js_error;
+
+use super::{
+    Protocol, TrackingConnection, TrackingMapping, TrackingReceiver, TrackingTransport,
+    TransportEvent,
+};
+
+/// A connection to a tracker, or to a relay forwarding its packets.
+pub type TrackingSocket = TrackingConnection<WebSocketTransport>;
+
+impl TrackingSocket {
+    /// Connects to a tracker, waking the event loop up with `wake` whenever something arrives.
+    pub fn connect(
+        url: &str,
+        protocol: Protocol,
+        mapping: TrackingMapping,
+        wake: Option<EventLoopProxy<()>>,
+    ) -> anyhow::Result<Self> {
+        let receiver = TrackingReceiver::new(protocol, mapping);
+        Self::new(WebSocketTransport::new(wake), url, receiver)
+    }
+}
+
+/// Queues what happens on a WebSocket until the next frame handles it.
+pub struct WebSocketTransport {
+    socket: Option<WebSocket>,
+    events: Rc<RefCell<Vec<TransportEvent>>>,
+    // Shared by every socket, as reconnecting replaces the socket
+    on_open: Closure<dyn FnMut(web_sys::Event)>,
+    on_message: Closure<dyn FnMut(MessageEvent)>,
+    on_close: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+impl WebSocketTransport {
+    pub fn new(wake: Option<EventLoopProxy<()>>) -> Self {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let push = {
+            let events = events.clone();
+            move |event| {
+                events.borrow_mut().push(event);
+                if let Some(wake) = &wake {
+                    // Only fails once the event loop is gone, and with it anything to render
+                    let _ = wake.send_event(());
+                }
+            }
+        };
+
+        let on_open_push = push.clone();
+        let on_open = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+            on_open_push(TransportEvent::Opened);
+        });
+
+        let on_message_push = push.clone();
+        let on_message = Closure::<dyn FnMut(_)>::new(move |event: MessageEvent| {
+            let data = event.data();
+            match data.dyn_into::<ArrayBuffer>() {
+                Ok(buf) => on_message_push(TransportEvent::Binary(Uint8Array::new(&buf).to_vec())),
+                Err(data) => match data.as_string() {
+                    Some(text) => on_message_push(TransportEvent::Text(text)),
+                    None => debug!("unexpected tracking message type"),
+                },
+            }
+        });
+
+        let on_close = Closure::<dyn FnMut(_)>::new(move |_: web_sys::Event| {
+            push(TransportEvent::Closed);
+        });
+
+        Self {
+            socket: None,
+            events,
+            on_open,
+            on_message,
+            on_close,
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            socket.set_onopen(None);
+            socket.set_onmessage(None);
+            socket.set_onclose(None);
+            let _ = socket.close();
+        }
+    }
+}
+
+impl TrackingTransport for WebSocketTransport {
+    fn connect(&mut self, url: &str) -> anyhow::Result<()> {
+        self.close();
+
+        let socket = WebSocket::new(url).map_err(js_error)?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        socket.set_onopen(Some(self.on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(self.on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(self.on_close.as_ref().unchecked_ref()));
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn take_events(&mut self) -> Vec<TransportEvent> {
+        std::mem::take(&mut self.events.borrow_mut())
+    }
+}
+
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
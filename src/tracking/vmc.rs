@@ -0,0 +1,211 @@
+//! The VMC protocol, sent as OSC by most VRM-based face trackers.
+
+use std::collections::HashMap;
+
+use glam::{EulerRot, Quat};
+
+use super::osc::OscMessage;
+use super::TrackingFrame;
+
+/// VRM expression presets standing for the shared names, in VRM 0.x and 1.0 spelling,
+/// lowercased. Most VMC senders only send these rather than ARKit blendshapes.
+const VRM_PRESETS: [(&str, &[&str]); 3] = [
+    ("EyeBlinkLeft", &["blink_l", "blinkleft", "blink"]),
+    ("EyeBlinkRight", &["blink_r", "blinkright", "blink"]),
+    ("JawOpen", &["a", "aa"]),
+];
+
+/// Buffers blendshapes until the tracker applies them, as the protocol requires.
+#[derive(Default)]
+pub struct VmcDecoder {
+    pending: HashMap<String, f32>,
+}
+
+impl VmcDecoder {
+    pub fn handle(&mut self, message: &OscMessage, frame: &mut TrackingFrame) {
+        let args = &message.args;
+        match message.address.as_str() {
+            "/VMC/Ext/Blend/Val" => {
+                if let (Some(name), Some(value)) = (
+                    args.first().and_then(|a| a.as_str()),
+                    args.get(1).and_then(|a| a.as_f32()),
+                ) {
+                    self.pending.insert(name.to_owned(), value);
+                }
+            }
+            "/VMC/Ext/Blend/Apply" => {
+                self.add_shared_names(frame);
+                frame.values.extend(self.pending.drain());
+            }
+            "/VMC/Ext/Bone/Pos" => {
+                if args.first().and_then(|a| a.as_str()) != Some("Head") {
+                    return;
+                }
+                let Some(v) = args[1..]
+                    .iter()
+                    .map(|a| a.as_f32())
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|v| v.len() == 7)
+                else {
+                    return;
+                };
+
+                frame.set_head_position(v[0], v[1], v[2]);
+                let (yaw, pitch, roll) = Quat::from_xyzw(v[3], v[4], v[5], v[6])
+                    .normalize()
+                    .to_euler(EulerRot::YXZ);
+                frame.set_head_rotation(yaw.to_degrees(), pitch.to_degrees(), roll.to_degrees());
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the shared names from the VRM presets of the pending batch, unless the sender
+    /// provides them itself, as "perfect sync" senders do.
+    fn add_shared_names(&self, frame: &mut TrackingFrame) {
+        for (shared, presets) in VRM_PRESETS {
+            if self.pending.contains_key(shared) {
+                continue;
+            }
+
+            // A blink of both eyes adds up with a single eye's
+            let value = self
+                .pending
+                .iter()
+                .filter(|(name, _)| presets.contains(&name.to_ascii_lowercase().as_str()))
+                .map(|(_, &value)| value)
+                .reduce(f32::max);
+            if let Some(value) = value {
+                frame.values.insert(shared.to_owned(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracking::osc::OscArg;
+
+    fn message(address: &str, args: Vec<OscArg>) -> OscMessage {
+        OscMessage {
+            address: address.to_owned(),
+            args,
+        }
+    }
+
+    fn blend(name: &str, value: f32) -> OscMessage {
+        message(
+            "/VMC/Ext/Blend/Val",
+            vec![OscArg::String(name.to_owned()), OscArg::Float(value)],
+        )
+    }
+
+    fn apply() -> OscMessage {
+        message("/VMC/Ext/Blend/Apply", vec![])
+    }
+
+    #[test]
+    fn blendshapes_wait_for_apply() {
+        let mut vmc = VmcDecoder::default();
+        let mut frame = TrackingFrame::default();
+
+        vmc.handle(&blend("JawOpen", 0.25), &mut frame);
+        vmc.handle(&blend("JawOpen", 0.5), &mut frame);
+        vmc.handle(&blend("EyeBlinkLeft", 1.0), &mut frame);
+        assert!(frame.values.is_empty());
+
+        vmc.handle(&apply(), &mut frame);
+        assert_eq!(frame.values["JawOpen"], 0.5);
+        assert_eq!(frame.values["EyeBlinkLeft"], 1.0);
+
+        // The next batch only replaces what it sends
+        vmc.handle(&blend("JawOpen", 0.0), &mut frame);
+        assert_eq!(frame.values["JawOpen"], 0.5);
+        vmc.handle(&apply(), &mut frame);
+        assert_eq!(frame.values["JawOpen"], 0.0);
+        assert_eq!(frame.values["EyeBlinkLeft"], 1.0);
+    }
+
+    #[test]
+    fn vrm_presets_provide_the_shared_names() {
+        let mut vmc = VmcDecoder::default();
+        let mut frame = TrackingFrame::default();
+
+        vmc.handle(&blend("Blink_L", 0.25), &mut frame);
+        vmc.handle(&blend("Blink", 0.5), &mut frame);
+        vmc.handle(&blend("A", 0.75), &mut frame);
+        vmc.handle(&apply(), &mut frame);
+        assert_eq!(frame.values["EyeBlinkLeft"], 0.5);
+        assert_eq!(frame.values["EyeBlinkRight"], 0.5);
+        assert_eq!(frame.values["JawOpen"], 0.75);
+        // The sender's names are kept too
+        assert_eq!(frame.values["Blink_L"], 0.25);
+
+        // VRM 1.0 names
+        vmc.handle(&blend("blinkRight", 1.0), &mut frame);
+        vmc.handle(&blend("aa", 0.0), &mut frame);
+        vmc.handle(&apply(), &mut frame);
+        assert_eq!(frame.values["EyeBlinkRight"], 1.0);
+        assert_eq!(frame.values["JawOpen"], 0.0);
+    }
+
+    #[test]
+    fn arkit_names_win_over_vrm_presets() {
+        let mut vmc = VmcDecoder::default();
+        let mut frame = TrackingFrame::default();
+
+        vmc.handle(&blend("JawOpen", 0.25), &mut frame);
+        vmc.handle(&blend("A", 1.0), &mut frame);
+        vmc.handle(&apply(), &mut frame);
+        assert_eq!(frame.values["JawOpen"], 0.25);
+    }
+
+    #[test]
+    fn head_bone_sets_the_pose() {
+        let mut vmc = VmcDecoder::default();
+        let mut frame = TrackingFrame::default();
+
+        // Turned 90° around the vertical axis
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let pose = [1.0, 2.0, 3.0, 0.0, half, 0.0, half];
+        let args = |bone: &str| {
+            let mut args = vec![OscArg::String(bone.to_owned())];
+            args.extend(pose.iter().map(|&v| OscArg::Float(v)));
+            args
+        };
+
+        vmc.handle(&message("/VMC/Ext/Bone/Pos", args("Hips")), &mut frame);
+        assert!(frame.values.is_empty());
+
+        vmc.handle(&message("/VMC/Ext/Bone/Pos", args("Head")), &mut frame);
+        assert_eq!(frame.values["HeadX"], 1.0);
+        assert_eq!(frame.values["HeadZ"], 3.0);
+        assert!((frame.values["HeadYaw"] - 90.0).abs() < 1e-3);
+        assert!(frame.values["HeadPitch"].abs() < 1e-3);
+        assert!(frame.values["HeadRoll"].abs() < 1e-3);
+    }
+
+    #[test]
+    fn ignores_malformed_messages() {
+        let mut vmc = VmcDecoder::default();
+        let mut frame = TrackingFrame::default();
+
+        let malformed = [
+            message("/VMC/Ext/Blend/Val", vec![]),
+            message("/VMC/Ext/Blend/Val", vec![OscArg::Float(1.0)]),
+            message("/VMC/Ext/Blend/Val", vec![OscArg::String("A".to_owned())]),
+            message("/VMC/Ext/Bone/Pos", vec![]),
+            message("/VMC/Ext/Bone/Pos", vec![OscArg::String("Head".to_owned())]),
+            message(
+                "/VMC/Ext/Bone/Pos",
+                vec![OscArg::String("Head".to_owned()), OscArg::Nil],
+            ),
+            apply(),
+        ];
+        for msg in &malformed {
+            vmc.handle(msg, &mut frame);
+        }
+        assert!(frame.values.is_empty());
+    }
+}
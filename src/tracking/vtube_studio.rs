@@ -0,0 +1,102 @@
+//! The JSON tracking data sent by the VTube Studio iPhone app.
+
+use anyhow::Context;
+
+use super::TrackingFrame;
+
+/// Decodes a message such as
+/// `{"FaceFound": true, "Rotation": {"x": 0, "y": 0, "z": 0}, "Position": {...},
+/// "BlendShapes": [{"k": "EyeBlinkLeft", "v": 0.1}, ...]}`.
+pub fn decode(message: &str, frame: &mut TrackingFrame) -> anyhow::Result<()> {
+    let root = json::parse(message)?;
+
+    if root["FaceFound"].as_bool() == Some(false) {
+        return Ok(());
+    }
+
+    let vec3 = |value: &json::JsonValue| -> anyhow::Result<(f32, f32, f32)> {
+        Ok((
+            value["x"].as_f32().context("missing x")?,
+            value["y"].as_f32().context("missing y")?,
+            value["z"].as_f32().context("missing z")?,
+        ))
+    };
+
+    if root.has_key("Rotation") {
+        let (pitch, yaw, roll) = vec3(&root["Rotation"]).context("invalid rotation")?;
+        frame.set_head_rotation(yaw, pitch, roll);
+    }
+    if root.has_key("Position") {
+        let (x, y, z) = vec3(&root["Position"]).context("invalid position")?;
+        frame.set_head_position(x, y, z);
+    }
+
+    // ARKit blendshapes, which are already the names used by the default mapping
+    for shape in root["BlendShapes"].members() {
+        if let (Some(name), Some(value)) = (shape["k"].as_str(), shape["v"].as_f32()) {
+            frame.values.insert(name.to_owned(), value);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_pose_and_blendshapes() {
+        let message = r#"{
+            "Timestamp": 1686000000000,
+            "Hotkey": -1,
+            "FaceFound": true,
+            "Rotation": {"x": 5.5, "y": -12, "z": 3},
+            "Position": {"x": 0.1, "y": -0.2, "z": 0.3},
+            "EyeLeft": {"x": 1, "y": 2, "z": 0},
+            "BlendShapes": [
+                {"k": "EyeBlinkLeft", "v": 0.25},
+                {"k": "JawOpen", "v": 0.5},
+                {"k": "MissingValue"},
+                {"v": 1}
+            ]
+        }"#;
+        let mut frame = TrackingFrame::default();
+        decode(message, &mut frame).unwrap();
+
+        let values = &frame.values;
+        assert_eq!(values["HeadPitch"], 5.5);
+        assert_eq!(values["HeadYaw"], -12.0);
+        assert_eq!(values["HeadRoll"], 3.0);
+        assert_eq!(values["HeadY"], -0.2);
+        assert_eq!(values["EyeBlinkLeft"], 0.25);
+        assert_eq!(values["JawOpen"], 0.5);
+        assert!(!values.contains_key("MissingValue"));
+    }
+
+    #[test]
+    fn lost_faces_keep_the_last_values() {
+        let mut frame = TrackingFrame::default();
+        decode(
+            r#"{"FaceFound": false, "Rotation": {"x": 1, "y": 2, "z": 3}}"#,
+            &mut frame,
+        )
+        .unwrap();
+        assert!(frame.values.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_messages() {
+        let invalid = [
+            "",
+            "not json",
+            r#"{"FaceFound": true"#,
+            r#"{"Rotation": {"x": 1, "y": 2}}"#,
+            r#"{"Position": [1, 2, 3]}"#,
+        ];
+        for message in invalid {
+            let mut frame = TrackingFrame::default();
+            assert!(decode(message, &mut frame).is_err(), "{message:?} decoded");
+        }
+    }
+}
@@ -0,0 +1,1194 @@
+//! The viewer state shared between the event loop and the JavaScript API.
+
+// Native builds have no JavaScript API to call most of it
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, RawWaker, RawWakerVTable, Waker};
+
+use anyhow::{anyhow, Context};
+use glam::{uvec2, vec2, UVec2, Vec2};
+use inox2d::math::camera::Camera;
+use inox2d::model::Model;
+use log::{debug, info, warn};
+use web_time::Instant;
+use wgpu::CompositeAlphaMode;
+use winit::dpi::PhysicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::Window;
+
+use crate::animation::Animator;
+use crate::background::Background;
+use crate::capabilities::Capabilities;
+use crate::capture::{self, CaptureOptions, Captures};
+use crate::idle::IdleAnimator;
+use crate::params;
+use crate::perf::{PerfMonitor, PerfStats};
+use crate::perf_hud::PerfHud;
+use crate::pointer_tracking::PointerTracker;
+use crate::puppet_scene::{PuppetScene, PuppetTransform};
+use crate::readback::Readback;
+use crate::recorder::{RecordOptions, Recorder, VideoFormat};
+use crate::scene::{self, ExampleSceneController};
+use crate::scheduler::{NextFrame, RenderScheduler, MIN_FPS_CAP};
+
+#[cfg(target_arch = "wasm32")]
+use crate::param_panel::ParamPanel;
+#[cfg(target_arch = "wasm32")]
+use crate::tracking::{ConnectionStatus, Protocol, TrackingMapping, TrackingSocket};
+#[cfg(target_arch = "wasm32")]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(target_arch = "wasm32")]
+use web_sys::HtmlCanvasElement;
+#[cfg(target_arch = "wasm32")]
+use winit::event_loop::EventLoopProxy;
+#[cfg(target_arch = "wasm32")]
+use winit::platform::web::{EventLoopExtWebSys, WindowExtWebSys};
+
+/// How often rendering had to recover from a lost surface or device.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecoveryStats {
+    pub surface_lost: u32,
+    pub surface_outdated: u32,
+    pub surface_timeouts: u32,
+    pub device_lost: u32,
+}
+
+/// A device and what is known about it.
+struct Gpu {
+    adapter: wgpu::Adapter,
+    capabilities: Capabilities,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Set once the device is lost, from wgpu's error handler.
+    device_lost: Arc<AtomicBool>,
+}
+
+pub struct Viewer {
+    window: Window,
+    instance: wgpu::Instance,
+    adapter_info: wgpu::AdapterInfo,
+    capabilities: Capabilities,
+    device_lost: Arc<AtomicBool>,
+    /// A replacement for a lost device, being requested.
+    pending_gpu: Option<Pin<Box<dyn Future<Output = anyhow::Result<Gpu>>>>>,
+    surface: wgpu::Surface,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+
+    camera: Camera,
+    scene_ctrl: ExampleSceneController,
+    scene: PuppetScene,
+    #[cfg(target_arch = "wasm32")]
+    panel: Option<ParamPanel>,
+    pointer_tracker: Option<PointerTracker>,
+    idle: Option<IdleAnimator>,
+    #[cfg(target_arch = "wasm32")]
+    tracker: Option<TrackingSocket>,
+    /// Wakes the event loop up from DOM callbacks, once it runs.
+    #[cfg(target_arch = "wasm32")]
+    proxy: Option<EventLoopProxy<()>>,
+    captures: Captures,
+    recorder: Option<Recorder>,
+    /// Bumped on every asynchronous load, so that a slow load can't replace a newer puppet.
+    load_generation: u64,
+
+    /// Keyframe animations applied to the selected puppet.
+    pub animator: Animator,
+
+    pub stats: RecoveryStats,
+    scheduler: RenderScheduler,
+    /// Measures frames while the HUD is shown or stats are reported.
+    perf: Option<PerfMonitor>,
+    hud: Option<PerfHud>,
+    on_stats: Option<Box<dyn FnMut(&PerfStats)>>,
+
+    /// Whether to render continuously, even when nothing changes.
+    running: bool,
+}
+
+impl Viewer {
+    pub async fn new(window: Window) -> anyhow::Result<Self> {
+        // Touch gestures drive the camera, so the browser mustn't scroll or zoom the page and
+        // cancel the pointers halfway through, which winit doesn't prevent
+        #[cfg(target_arch = "wasm32")]
+        window
+            .canvas()
+            .style()
+            .set_property("touch-action", "none")
+            .map_err(crate::loader::js_error)?;
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let surface = unsafe { instance.create_surface(&window) }?;
+        let Gpu {
+            adapter,
+            capabilities,
+            device,
+            queue,
+            device_lost,
+        } = request_gpu(&instance, &surface).await?;
+        let adapter_info = adapter.get_info();
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        let format = choose_format(&surface_caps.formats)?;
+        info!(
+            "surface format: {format:?}, offered: {:?}",
+            surface_caps.formats
+        );
+
+        // Frames are premultiplied, so only PreMultiplied shows transparent backgrounds
+        // correctly. Otherwise fall back to the first mode offered, usually Opaque (or Auto
+        // on WebGL, where the browser composites the canvas as premultiplied anyway), in
+        // which case transparent areas show up black.
+        let alpha_modes = surface_caps.alpha_modes;
+        let alpha_mode = if alpha_modes.contains(&CompositeAlphaMode::PreMultiplied) {
+            CompositeAlphaMode::PreMultiplied
+        } else {
+            warn!(
+                "surface alpha modes {alpha_modes:?} don't include PreMultiplied, using {:?}",
+                alpha_modes[0]
+            );
+            alpha_modes[0]
+        };
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: window.inner_size().width,
+            height: window.inner_size().height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode,
+            view_formats: Vec::new(),
+        };
+        surface.configure(&device, &config);
+
+        info!("wgpu surface initialized");
+
+        let scene = PuppetScene::new(&device, config.format, uvec2(config.width, config.height));
+        let camera = Camera::default();
+        let mut scene_ctrl = ExampleSceneController::new(&camera, 0.5);
+        scene_ctrl.viewport = vec2(config.width as f32, config.height as f32);
+
+        // The first frame shows whatever was set up before the event loop started
+        let mut scheduler = RenderScheduler::default();
+        scheduler.invalidate();
+
+        Ok(Self {
+            window,
+            instance,
+            adapter_info,
+            capabilities,
+            device_lost,
+            pending_gpu: None,
+            surface,
+            device,
+            queue,
+            config,
+            camera,
+            scene_ctrl,
+            scene,
+            #[cfg(target_arch = "wasm32")]
+            panel: None,
+            pointer_tracker: None,
+            idle: None,
+            #[cfg(target_arch = "wasm32")]
+            tracker: None,
+            #[cfg(target_arch = "wasm32")]
+            proxy: None,
+            captures: Captures::default(),
+            recorder: None,
+            load_generation: 0,
+            animator: Animator::default(),
+            stats: RecoveryStats::default(),
+            scheduler,
+            perf: None,
+            hud: None,
+            on_stats: None,
+            running: false,
+        })
+    }
+
+    /// The graphics API rendering is done with, e.g. "WebGPU" or "WebGL2".
+    pub fn backend_name(&self) -> &'static str {
+        match self.adapter_info.backend {
+            wgpu::Backend::BrowserWebGpu => "WebGPU",
+            wgpu::Backend::Gl if cfg!(target_arch = "wasm32") => "WebGL2",
+            wgpu::Backend::Gl => "OpenGL",
+            wgpu::Backend::Vulkan => "Vulkan",
+            wgpu::Backend::Metal => "Metal",
+            wgpu::Backend::Dx12 => "DirectX 12",
+            wgpu::Backend::Dx11 => "DirectX 11",
+            wgpu::Backend::Empty => "no backend",
+        }
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn canvas(&self) -> HtmlCanvasElement {
+        self.window.canvas()
+    }
+
+    /// Replaces every puppet in the scene with the given model.
+    pub fn load_puppet(&mut self, model: Model) -> anyhow::Result<()> {
+        // Keep the current puppets if the new one can't be shown
+        self.capabilities.check_model(&model)?;
+
+        // Free the previous puppets' textures before uploading the new ones
+        self.unload_puppet();
+
+        self.upload_puppet(model);
+        self.camera.scale = Vec2::splat(0.15);
+        self.scene_ctrl.reset(&self.camera);
+        Ok(())
+    }
+
+    /// Adds a puppet on top of the scene, returning its id.
+    pub fn add_puppet(&mut self, model: Model) -> anyhow::Result<u32> {
+        self.capabilities.check_model(&model)?;
+        Ok(self.upload_puppet(model))
+    }
+
+    /// Adds a puppet already checked to fit on the device.
+    fn upload_puppet(&mut self, model: Model) -> u32 {
+        log_model_info(&model);
+        let id = self.scene.add(&self.device, &self.queue, model);
+        self.wake();
+        id
+    }
+
+    /// Removes a single puppet from the scene, releasing its GPU resources.
+    pub fn remove_puppet(&mut self, id: u32) -> bool {
+        let removed = self.scene.remove(id);
+        if removed {
+            self.device.poll(wgpu::Maintain::Poll);
+            self.wake();
+        }
+        removed
+    }
+
+    /// Removes every puppet, releasing their GPU resources.
+    pub fn unload_puppet(&mut self) {
+        if !self.scene.is_empty() {
+            self.scene.clear();
+            self.device.poll(wgpu::Maintain::Poll);
+            info!("puppets unloaded");
+        }
+        self.wake();
+    }
+
+    /// Starts an asynchronous load, returning a ticket to pass to [`Self::finish_load`].
+    pub fn begin_load(&mut self) -> u64 {
+        self.load_generation += 1;
+        self.load_generation
+    }
+
+    /// Shows a puppet loaded asynchronously, unless another load was started meanwhile.
+    pub fn finish_load(&mut self, ticket: u64, model: Model) -> anyhow::Result<()> {
+        if ticket == self.load_generation {
+            self.load_puppet(model)
+        } else {
+            debug!("discarding outdated puppet load");
+            Ok(())
+        }
+    }
+
+    /// Sets a parameter value of the selected puppet, applied on every frame.
+    pub fn set_param(&mut self, name: &str, value: Vec2) -> anyhow::Result<()> {
+        let selected = self.scene.selected_mut().context("no puppet selected")?;
+        if !selected.model.puppet.parameters.contains_key(name) {
+            return Err(anyhow!("puppet has no parameter named {name:?}"));
+        }
+
+        selected.params.insert(name.to_owned(), value);
+        self.wake();
+        Ok(())
+    }
+
+    pub fn reset_params(&mut self) {
+        if let Some(selected) = self.scene.selected_mut() {
+            selected.params.clear();
+        }
+        self.wake();
+    }
+
+    pub fn select(&mut self, id: Option<u32>) {
+        self.scene.selected = id;
+        self.wake();
+    }
+
+    pub fn set_transform(&mut self, id: u32, transform: PuppetTransform) -> anyhow::Result<()> {
+        let puppet = self.scene.get_mut(id).context("no puppet with this id")?;
+        puppet.transform = transform;
+        self.wake();
+        Ok(())
+    }
+
+    /// Shows sliders for the selected puppet's parameters below the canvas.
+    #[cfg(target_arch = "wasm32")]
+    pub fn show_param_panel(&mut self) -> anyhow::Result<()> {
+        if self.panel.is_none() {
+            self.panel = Some(ParamPanel::new(&self.window.canvas(), self.proxy.clone())?);
+        }
+        Ok(())
+    }
+
+    /// Makes the selected puppet follow the cursor, or stops it with `None`.
+    pub fn set_pointer_tracking(&mut self, tracker: Option<PointerTracker>) {
+        self.pointer_tracker = tracker;
+        self.wake();
+    }
+
+    /// Animates the selected puppet with blinking, breathing and swaying, or stops it with `None`.
+    pub fn set_idle_animation(&mut self, idle: Option<IdleAnimator>) {
+        self.idle = idle;
+        self.wake();
+    }
+
+    /// Drives the selected puppet from a face tracker, replacing any connected one.
+    #[cfg(target_arch = "wasm32")]
+    pub fn connect_tracker(
+        &mut self,
+        url: &str,
+        protocol: Protocol,
+        mapping: TrackingMapping,
+    ) -> anyhow::Result<()> {
+        let tracker = TrackingSocket::connect(url, protocol, mapping, self.proxy.clone())?;
+        self.tracker = Some(tracker);
+        self.wake();
+        Ok(())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn disconnect_tracker(&mut self) {
+        self.tracker = None;
+        self.wake();
+    }
+
+    /// Where the connection to the face tracker stands, if one is connected.
+    #[cfg(target_arch = "wasm32")]
+    pub fn tracker_status(&self) -> Option<ConnectionStatus> {
+        self.tracker.as_ref().map(TrackingSocket::status)
+    }
+
+    /// Uses the tracked user's current head pose as the neutral one.
+    #[cfg(target_arch = "wasm32")]
+    pub fn calibrate_tracker(&mut self) -> anyhow::Result<()> {
+        self.tracker
+            .as_mut()
+            .context("no tracker connected")?
+            .calibrate();
+        Ok(())
+    }
+
+    /// Sets what puppets are drawn over.
+    pub fn set_background(&mut self, background: Background) -> anyhow::Result<()> {
+        self.scene
+            .set_background(&self.device, &self.queue, background)?;
+        self.wake();
+        Ok(())
+    }
+
+    pub fn set_camera(&mut self, position: Vec2, scale: f32) {
+        self.camera.position = position;
+        self.camera.scale = Vec2::splat(scale);
+        self.scene_ctrl.reset(&self.camera);
+        self.wake();
+    }
+
+    /// Captures the next frame as a PNG, passed to `callback` once read back.
+    pub fn capture(
+        &mut self,
+        options: CaptureOptions,
+        callback: impl FnOnce(anyhow::Result<Vec<u8>>) + 'static,
+    ) {
+        self.captures.request(options, callback);
+        self.wake();
+    }
+
+    /// Starts recording frames, replacing any recording in progress.
+    pub fn start_recording(&mut self, options: RecordOptions) -> anyhow::Result<()> {
+        let recorder = Recorder::new(options)?;
+        self.scene_ctrl.set_fixed_step(recorder.fixed_step());
+        self.recorder = Some(recorder);
+        self.fit_scene();
+        self.wake();
+        Ok(())
+    }
+
+    /// Stops recording, `callback` receiving the encoded clip once the last frames are read back.
+    pub fn stop_recording(
+        &mut self,
+        callback: impl FnOnce(anyhow::Result<Vec<u8>>) + 'static,
+    ) -> anyhow::Result<()> {
+        let recorder = self.recorder.as_mut().context("not recording")?;
+        recorder.stop(callback);
+        self.scene_ctrl.set_fixed_step(None);
+        self.fit_scene();
+        self.wake();
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// How much larger than the window puppets are rendered.
+    ///
+    /// While recording, that's at the recording's size, so that its frames don't resize the
+    /// puppets' targets twice each. The window shows them scaled back down.
+    fn scene_scale(&self) -> f32 {
+        let window_size = uvec2(self.config.width, self.config.height);
+        let max = self.device.limits().max_texture_dimension_2d;
+        self.recorder
+            .as_ref()
+            .filter(|r| r.is_recording())
+            .map(|r| r.options.scale)
+            .filter(|&scale| {
+                let size = scaled_size(window_size, scale);
+                size.min_element() > 0 && size.max_element() <= max
+            })
+            .unwrap_or(1.0)
+    }
+
+    /// Resizes the puppets' targets if the window or the recording changed their size.
+    fn fit_scene(&mut self) {
+        let window_size = uvec2(self.config.width, self.config.height);
+        let size = scaled_size(window_size, self.scene_scale());
+        if size != self.scene.size() {
+            self.scene.resize(&self.device, size);
+        }
+    }
+
+    /// Renders the scene into a texture that can be read back, at a multiple of the window size.
+    fn render_capture(&mut self, options: CaptureOptions) -> anyhow::Result<Readback> {
+        let window_size = uvec2(self.config.width, self.config.height);
+        let size = scaled_size(window_size, options.scale);
+        let max = self.device.limits().max_texture_dimension_2d;
+        if size.min_element() == 0 || size.max_element() > max {
+            return Err(anyhow!(
+                "can't capture at {size}, the limit is {max} pixels"
+            ));
+        }
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("capture"),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Zoom in along with the size, so that the capture is framed like the window
+        let scale = self.camera.scale;
+        self.camera.scale *= options.scale;
+        let scene_size = self.scene.size();
+        if size != scene_size {
+            self.scene.resize(&self.device, size);
+        }
+        self.scene.render(
+            &self.device,
+            &self.queue,
+            &self.camera,
+            &view,
+            !options.transparent,
+        );
+        if size != scene_size {
+            self.scene.resize(&self.device, scene_size);
+        }
+        self.camera.scale = scale;
+
+        Readback::new(&self.device, &self.queue, &texture)
+    }
+
+    /// Starts replacing a lost device, or swaps in the replacement once it is ready.
+    ///
+    /// Returns whether the device is usable.
+    fn recover_device(&mut self) -> bool {
+        if !self.device_lost.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let pending = self.pending_gpu.get_or_insert_with(|| {
+            self.stats.device_lost += 1;
+            warn!(
+                "GPU device lost ({} times so far), recreating it",
+                self.stats.device_lost
+            );
+            Box::pin(request_gpu(&self.instance, &self.surface))
+        });
+
+        // Polled on every frame rather than awaited, as the event loop can't wait
+        let gpu = match poll_now(pending.as_mut()) {
+            Poll::Pending => return false,
+            Poll::Ready(gpu) => gpu,
+        };
+        self.pending_gpu = None;
+        let gpu = match gpu {
+            Ok(gpu) => gpu,
+            Err(e) => {
+                log::error!("couldn't recreate the GPU device, retrying: {e}");
+                return false;
+            }
+        };
+
+        self.adapter_info = gpu.adapter.get_info();
+        self.capabilities = gpu.capabilities;
+        self.device = gpu.device;
+        self.queue = gpu.queue;
+        self.device_lost = gpu.device_lost;
+        self.surface.configure(&self.device, &self.config);
+
+        // Readbacks from the old device will never land
+        self.captures.cancel();
+        if self.perf.is_some() {
+            self.perf = Some(PerfMonitor::new(
+                &self.device,
+                &self.queue,
+                &self.capabilities,
+            ));
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.discard_pending();
+        }
+
+        match self.scene.recreate(&self.device, &self.queue) {
+            Ok(()) => info!("GPU device recreated"),
+            Err(e) => log::error!("couldn't restore the scene on the new device: {e}"),
+        }
+        true
+    }
+
+    /// Reconfigures the surface with the new size, as far as the device allows.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        let max = self.capabilities.max_texture_size();
+        if size.width > max || size.height > max {
+            warn!("window is larger than {max} pixels, the device's limit");
+        }
+        self.config.width = size.width.min(max);
+        self.config.height = size.height.min(max);
+        self.surface.configure(&self.device, &self.config);
+
+        // Update the renderers' internal viewports
+        self.fit_scene();
+        self.scene_ctrl.viewport = uvec2(self.config.width, self.config.height).as_vec2();
+
+        // On macos the window needs to be redrawn manually after resizing
+        self.wake();
+    }
+
+    /// Sets how many surface pixels a pixel of cursor movement is, when they differ.
+    #[cfg(target_arch = "wasm32")]
+    pub fn set_cursor_scale(&mut self, scale: f32) {
+        self.scene_ctrl.cursor_scale = scale;
+    }
+
+    /// Asks for a frame showing a change made from outside the event loop.
+    ///
+    /// On the web, requesting a redraw doesn't wake a waiting event loop up, an event does.
+    pub fn wake(&self) {
+        self.window.request_redraw();
+        #[cfg(target_arch = "wasm32")]
+        if let Some(proxy) = &self.proxy {
+            // Only fails once the event loop is gone, and with it anything to render
+            let _ = proxy.send_event(());
+        }
+    }
+
+    /// Renders continuously even when nothing changes, or only on changes again.
+    pub fn set_running(&mut self, running: bool) {
+        self.running = running;
+        self.wake();
+    }
+
+    /// Caps the frame rate, or lifts the cap with `None`.
+    pub fn set_fps_cap(&mut self, fps: Option<f32>) -> anyhow::Result<()> {
+        if let Some(fps) = fps.filter(|&fps| fps < MIN_FPS_CAP || !fps.is_finite()) {
+            return Err(anyhow!(
+                "frame rate cap must be at least {MIN_FPS_CAP}, not {fps}"
+            ));
+        }
+        self.scheduler.set_fps_cap(fps);
+        self.wake();
+        Ok(())
+    }
+
+    /// Stops rendering while the viewer can't be seen.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        debug!("viewer {}", if hidden { "hidden" } else { "shown" });
+        self.scheduler.set_hidden(hidden);
+        if !hidden {
+            self.wake();
+        }
+    }
+
+    /// Whether the scene changes by itself, without any input.
+    fn is_animating(&self) -> bool {
+        #[cfg(target_arch = "wasm32")]
+        let tracking = self.tracker.is_some();
+        #[cfg(not(target_arch = "wasm32"))]
+        let tracking = false;
+
+        // Input sources only drive the selected puppet
+        let driven = self.scene.selected().is_some()
+            && (self.animator.is_active()
+                || self.idle.is_some()
+                || self.pointer_tracker.is_some()
+                || tracking);
+
+        // Readbacks are only polled while rendering
+        let reading_back = self.captures.is_busy()
+            || self.recorder.as_ref().is_some_and(Recorder::needs_frames)
+            || self.scene.is_picking();
+
+        driven || reading_back || self.scene_ctrl.is_easing(&self.camera)
+    }
+
+    /// Requests the next frame once it is due, or waits for a change.
+    fn schedule(&mut self, control_flow: &mut ControlFlow) {
+        if *control_flow == ControlFlow::Exit {
+            return;
+        }
+        if self.running || self.is_animating() {
+            self.scheduler.invalidate();
+        }
+
+        match self.scheduler.next_frame(Instant::now()) {
+            NextFrame::Idle => control_flow.set_wait(),
+            NextFrame::Now => {
+                // Keeps the loop going until the frame is drawn, as waiting would stall it on
+                // the web until some unrelated event comes in
+                self.window.request_redraw();
+                control_flow.set_poll();
+            }
+            NextFrame::After(delay) => control_flow.set_wait_timeout(delay),
+        }
+    }
+
+    fn redraw(&mut self) {
+        if !self.recover_device() {
+            self.window.request_redraw();
+            return;
+        }
+
+        let frame_start = Instant::now();
+        let resumed = self.scheduler.rendered(frame_start);
+        if resumed {
+            // Animations carry on where they stopped, instead of catching up on the idle time
+            self.scene_ctrl.resume();
+        }
+        if let Some(perf) = &mut self.perf {
+            perf.begin_frame(frame_start, resumed);
+        }
+
+        // Grab the puppet under the cursor once the pick readback lands
+        if let Some(Some(id)) = self.scene.poll_pick(&self.device) {
+            self.scene.selected = Some(id);
+            if let Some(selected) = self.scene.selected() {
+                self.scene_ctrl.grab(selected.transform.position);
+            }
+        }
+
+        self.scene_ctrl.update(&mut self.camera);
+
+        if let Some(position) = self.scene_ctrl.grabbed_position(&self.camera) {
+            if let Some(selected) = self.scene.selected_mut() {
+                selected.transform.position = position;
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        if let Some(panel) = &mut self.panel {
+            match self.scene.selected_mut() {
+                Some(p) => panel.sync(Some(p.id), Some(&p.model.puppet), &mut p.params),
+                None => panel.sync(None, None, &mut HashMap::new()),
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        if let Some(tracker) = &mut self.tracker {
+            tracker.update(frame_start);
+        }
+
+        // Values driven by input sources, only applied to the selected puppet
+        let params_start = Instant::now();
+        let mut driven = HashMap::new();
+        if let Some(selected) = self.scene.selected() {
+            if let Some(idle) = &mut self.idle {
+                let t = self.scene_ctrl.current_elapsed();
+                idle.write(t, &selected.model.puppet, &mut driven);
+            }
+
+            self.animator.update(self.scene_ctrl.frame_delta());
+            self.animator.write(&selected.model.puppet, &mut driven);
+
+            if let Some(tracker) = &mut self.pointer_tracker {
+                let viewport = vec2(self.config.width as f32, self.config.height as f32);
+                let center = scene::world_to_screen(selected.transform.position, &self.camera)
+                    + viewport / 2.0;
+                let cursor = (self.scene_ctrl.mouse_pos() - center) / (viewport / 2.0);
+
+                tracker.update(cursor, self.scene_ctrl.frame_delta());
+                tracker.write(&selected.model.puppet, &mut driven);
+            }
+
+            #[cfg(target_arch = "wasm32")]
+            if let Some(tracker) = &mut self.tracker {
+                tracker.write(
+                    &selected.model.puppet,
+                    &mut driven,
+                    self.scene_ctrl.frame_delta(),
+                );
+            }
+        }
+
+        let selected = self.scene.selected;
+        for p in self.scene.puppets_mut() {
+            p.model.puppet.begin_set_params();
+            params::apply(&mut p.model.puppet, &p.params);
+            if Some(p.id) == selected {
+                params::apply(&mut p.model.puppet, &driven);
+            }
+            p.model.puppet.end_set_params();
+        }
+        if let Some(perf) = &mut self.perf {
+            perf.add_params_time(params_start.elapsed());
+        }
+
+        for (options, callback) in self.captures.take_requests() {
+            let readback = self.render_capture(options);
+            self.captures.start(readback, options, callback);
+        }
+        self.captures.poll(&self.device);
+
+        // Record as many frames as are due at the recording's frame rate
+        let dt = self.scene_ctrl.frame_delta();
+        let due = self
+            .recorder
+            .as_mut()
+            .map(|r| (r.frames_due(dt), r.capture_options()));
+        if let Some((due @ 1.., options)) = due {
+            let readback = self.render_capture(options);
+            if let Some(recorder) = &mut self.recorder {
+                recorder.push(readback, due);
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.poll(&self.device);
+        }
+        if self.recorder.as_ref().is_some_and(Recorder::is_done) {
+            if let Some(recorder) = self.recorder.take() {
+                recorder.finish();
+            }
+        }
+        // Back to the window's size once done or failed
+        self.fit_scene();
+
+        if self.captures.is_busy() || self.recorder.as_ref().is_some_and(Recorder::needs_frames) {
+            // Keep polling until the readbacks land
+            self.window.request_redraw();
+        }
+
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            Err(e) => {
+                match e {
+                    wgpu::SurfaceError::Lost => {
+                        self.stats.surface_lost += 1;
+                        debug!("surface lost, reconfiguring it ({:?})", self.stats);
+                        self.surface.configure(&self.device, &self.config);
+                    }
+                    // E.g. resized before the resize event came through
+                    wgpu::SurfaceError::Outdated => {
+                        self.stats.surface_outdated += 1;
+                        debug!("surface outdated, reconfiguring it ({:?})", self.stats);
+                        self.surface.configure(&self.device, &self.config);
+                    }
+                    // The frame is skipped, the next one will likely make it
+                    wgpu::SurfaceError::Timeout => {
+                        self.stats.surface_timeouts += 1;
+                        debug!("surface timed out, skipping a frame ({:?})", self.stats);
+                    }
+                    wgpu::SurfaceError::OutOfMemory => {
+                        self.device_lost.store(true, Ordering::Relaxed);
+                    }
+                }
+                self.window.request_redraw();
+                return;
+            }
+        };
+        let view = (output.texture).create_view(&wgpu::TextureViewDescriptor::default());
+
+        let render_start = Instant::now();
+        if let Some(perf) = &mut self.perf {
+            perf.begin_gpu(&self.device, &self.queue);
+        }
+        let scale = self.camera.scale;
+        self.camera.scale *= self.scene_scale();
+        self.scene
+            .render(&self.device, &self.queue, &self.camera, &view, true);
+        self.camera.scale = scale;
+        if let Some(perf) = &mut self.perf {
+            perf.end_gpu(&self.device, &self.queue);
+            perf.add_render_time(render_start.elapsed());
+        }
+        output.present();
+
+        let stats = self
+            .perf
+            .as_mut()
+            .and_then(|perf| perf.end_frame(&self.device, || self.scene.cost()));
+        if let Some(stats) = stats {
+            if let Some(hud) = &self.hud {
+                hud.show(&stats);
+            }
+            if let Some(on_stats) = &mut self.on_stats {
+                on_stats(&stats);
+            }
+        }
+    }
+
+    /// Shows frame timing stats over the canvas, or in the log natively, or hides them.
+    pub fn set_hud(&mut self, shown: bool) -> anyhow::Result<()> {
+        self.hud = match (shown, self.hud.take()) {
+            (false, _) => None,
+            (true, Some(hud)) => Some(hud),
+            #[cfg(target_arch = "wasm32")]
+            (true, None) => Some(PerfHud::new(&self.window.canvas())?),
+            #[cfg(not(target_arch = "wasm32"))]
+            (true, None) => Some(PerfHud::default()),
+        };
+        self.update_perf();
+        Ok(())
+    }
+
+    /// Hands frame timing stats to `callback` every second, or stops with `None`.
+    ///
+    /// `callback` runs in the middle of a redraw, so it mustn't touch the viewer itself.
+    pub fn set_stats_callback(&mut self, callback: Option<Box<dyn FnMut(&PerfStats)>>) {
+        self.on_stats = callback;
+        self.update_perf();
+    }
+
+    /// Only measures frames while someone looks at the results.
+    fn update_perf(&mut self) {
+        let wanted = self.hud.is_some() || self.on_stats.is_some();
+        if wanted != self.perf.is_some() {
+            self.perf =
+                wanted.then(|| PerfMonitor::new(&self.device, &self.queue, &self.capabilities));
+        }
+        self.wake();
+    }
+
+    pub fn handle_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
+        match event {
+            Event::RedrawRequested(_) => {
+                // Whoever asked for a redraw saw a change, but it may have to wait for the cap
+                self.scheduler.invalidate();
+                if self.scheduler.is_due(Instant::now()) {
+                    self.redraw();
+                } else {
+                    self.schedule(control_flow);
+                }
+            }
+            Event::WindowEvent { ref event, .. } => match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F),
+                            ..
+                        },
+                    ..
+                } => {
+                    // Toggle following the cursor
+                    let tracker = match self.pointer_tracker {
+                        Some(_) => None,
+                        None => Some(PointerTracker::default()),
+                    };
+                    self.set_pointer_tracking(tracker);
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::I),
+                            ..
+                        },
+                    ..
+                } => {
+                    // Toggle the idle animation
+                    let idle = match self.idle {
+                        Some(_) => None,
+                        None => Some(IdleAnimator::default()),
+                    };
+                    self.set_idle_animation(idle);
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::P),
+                            ..
+                        },
+                    ..
+                } => {
+                    // Toggle the performance HUD
+                    if let Err(e) = self.set_hud(self.hud.is_none()) {
+                        log::error!("couldn't show the performance HUD: {e}");
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::S),
+                            ..
+                        },
+                    ..
+                } => {
+                    // Save a screenshot
+                    self.capture(CaptureOptions::default(), |png| {
+                        if let Err(e) = png.and_then(|png| capture::save(&png, "png", "image/png"))
+                        {
+                            log::error!("couldn't save screenshot: {e}");
+                        }
+                    });
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::R),
+                            ..
+                        },
+                    ..
+                } => {
+                    // Toggle recording a clip, saved when stopped
+                    let res = if self.is_recording() {
+                        let format = self
+                            .recorder
+                            .as_ref()
+                            .map_or(VideoFormat::Gif, |r| r.options.format);
+                        self.stop_recording(move |clip| {
+                            let saved = clip.and_then(|clip| {
+                                capture::save(&clip, format.extension(), format.mime())
+                            });
+                            if let Err(e) = saved {
+                                log::error!("couldn't save recording: {e}");
+                            }
+                        })
+                    } else {
+                        self.start_recording(RecordOptions::default())
+                    };
+                    if let Err(e) = res {
+                        log::error!("{e}");
+                    }
+                }
+                #[cfg(target_arch = "wasm32")]
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::C),
+                            ..
+                        },
+                    ..
+                } => {
+                    // Copy a screenshot, the clipboard write must start while handling the key
+                    let (tx, rx) = futures_channel::oneshot::channel();
+                    self.capture(CaptureOptions::default(), move |png| {
+                        let _ = tx.send(png);
+                    });
+                    let res = crate::export::copy_png(async move { rx.await? });
+                    if let Err(e) = res {
+                        log::error!("couldn't copy screenshot: {e}");
+                    }
+                }
+                WindowEvent::Resized(size) => self.resize(*size),
+                WindowEvent::Occluded(occluded) => self.set_hidden(*occluded),
+                _ => {
+                    self.scene_ctrl.interact(&self.window, event, &self.camera);
+
+                    if let WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } = event
+                    {
+                        let pixel = (self.scene_ctrl.mouse_pos() * self.scene_scale()).as_uvec2();
+                        self.scene.request_pick(&self.device, &self.queue, pixel);
+                        self.window.request_redraw();
+                    }
+                }
+            },
+            // Sent by `wake` when something outside winit changed the scene
+            Event::UserEvent(()) => self.window.request_redraw(),
+            Event::MainEventsCleared => self.schedule(control_flow),
+            _ => {}
+        }
+    }
+}
+
+/// Requests an adapter able to present to `surface`, and a device with what it supports.
+fn request_gpu(
+    instance: &wgpu::Instance,
+    surface: &wgpu::Surface,
+) -> impl Future<Output = anyhow::Result<Gpu>> + 'static {
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: Some(surface),
+        force_fallback_adapter: false,
+    });
+
+    async move {
+        let adapter = adapter.await.ok_or(anyhow!("no wgpu adapter found"))?;
+        info!("wgpu adapter: {:?}", adapter.get_info());
+
+        let capabilities = Capabilities::negotiate(&adapter);
+        let (device, queue) = adapter
+            .request_device(&capabilities.device_descriptor(), None)
+            .await?;
+        info!("device features: {:?}", device.features());
+
+        // wgpu panics on errors by default, log them instead and watch for device loss
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            log::error!("wgpu error: {error}");
+            let is_loss = match &error {
+                wgpu::Error::OutOfMemory { .. } => true,
+                wgpu::Error::Validation { description, .. } => {
+                    description.to_lowercase().contains("lost")
+                }
+            };
+            if is_loss {
+                lost.store(true, Ordering::Relaxed);
+            }
+        }));
+
+        Ok(Gpu {
+            adapter,
+            capabilities,
+            device,
+            queue,
+            device_lost,
+        })
+    }
+}
+
+/// Polls a future once, without anything to wake up when it makes progress.
+fn poll_now<T>(future: Pin<&mut dyn Future<Output = T>>) -> Poll<T> {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    // Safety: the vtable's functions do nothing, so they uphold every contract
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    future.poll(&mut TaskContext::from_waker(&waker))
+}
+
+/// Picks the surface format that needs the least conversion.
+///
+/// inox2d outputs sRGB values as is, so linear formats show them unchanged. sRGB formats
+/// would encode them a second time, so the compositor decodes them first in that case.
+fn choose_format(formats: &[wgpu::TextureFormat]) -> anyhow::Result<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+
+    // Browsers may only offer RGBA, and only 8-bit formats can be captured
+    [Bgra8Unorm, Rgba8Unorm, Bgra8UnormSrgb, Rgba8UnormSrgb]
+        .into_iter()
+        .find(|format| formats.contains(format))
+        .or(formats.first().copied())
+        .context("surface is incompatible with the adapter")
+}
+
+/// Starts the event loop without blocking, driving the given viewer.
+#[cfg(target_arch = "wasm32")]
+pub fn spawn_event_loop(event_loop: EventLoop<()>, viewer: Rc<RefCell<Viewer>>) {
+    viewer.borrow_mut().proxy = Some(event_loop.create_proxy());
+    if let Err(e) = pause_when_hidden(viewer.clone()) {
+        log::error!("couldn't watch the page's visibility: {e}");
+    }
+
+    event_loop
+        .spawn(move |event, _, control_flow| viewer.borrow_mut().handle_event(event, control_flow));
+}
+
+/// Stops rendering while the page is in a background tab or minimized.
+#[cfg(target_arch = "wasm32")]
+fn pause_when_hidden(viewer: Rc<RefCell<Viewer>>) -> anyhow::Result<()> {
+    use wasm_bindgen::{closure::Closure, JsCast};
+
+    let document = web_sys::window()
+        .and_then(|win| win.document())
+        .context("no document")?;
+
+    // Pages opened in a background tab start out hidden
+    viewer.borrow_mut().scheduler.set_hidden(document.hidden());
+
+    let watched = document.clone();
+    let on_change = Closure::<dyn FnMut()>::new(move || {
+        viewer.borrow_mut().set_hidden(watched.hidden());
+    });
+    document
+        .add_event_listener_with_callback("visibilitychange", on_change.as_ref().unchecked_ref())
+        .map_err(crate::loader::js_error)?;
+    on_change.forget();
+    Ok(())
+}
+
+/// Runs the event loop on the current thread until the window is closed.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_event_loop(event_loop: EventLoop<()>, mut viewer: Viewer) -> ! {
+    event_loop.run(move |event, _, control_flow| viewer.handle_event(event, control_flow))
+}
+
+fn log_model_info(model: &Model) {
+    info!("== Puppet Meta ==\n{}", &model.puppet.meta);
+    debug!("== Nodes ==\n{}", &model.puppet.nodes);
+    if model.vendors.is_empty() {
+        info!("(No Vendor Data)\n");
+    } else {
+        info!("== Vendor Data ==");
+        for vendor in &model.vendors {
+            debug!("{vendor}");
+        }
+    }
+}
+
+/// A multiple of the window size, in whole pixels.
+fn scaled_size(window_size: UVec2, scale: f32) -> UVec2 {
+    (window_size.as_vec2() * scale).round().as_uvec2()
+}
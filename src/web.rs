@@ -0,0 +1,222 @@
+//! Entry point for the web build, spawning the default viewer.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Context};
+use js_sys::{Function, Promise, Reflect};
+use log::info;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Element, HtmlCanvasElement};
+use winit::platform::web::WindowExtWebSys;
+use winit::window::Window;
+use winit::{event_loop::EventLoop, window::WindowBuilder};
+
+use crate::background::Background;
+use crate::canvas_fit;
+use crate::dropzone;
+use crate::loader::{js_error, query_param, PuppetSource};
+use crate::tracking::{Protocol, TrackingMapping};
+use crate::viewer::{self, Viewer};
+
+pub fn main() {
+    wasm_logger::init(wasm_logger::Config::new(log::Level::Info));
+    console_error_panic_hook::set_once();
+
+    // Pages embedding the viewer through the JS API opt out of the default one
+    if !manual_mode() {
+        wasm_bindgen_futures::spawn_local(runwrap());
+    }
+}
+
+async fn runwrap() {
+    match run().await {
+        Ok(_) => info!("app shutdown"),
+        Err(e) => {
+            log::error!("error: {}", e);
+            // There may be no canvas yet, and the page would stay blank without a word
+            if let Err(e) = show_error(&format!("{e:#}")) {
+                log::error!("couldn't show the error: {e}");
+            }
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let host = web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| doc.body())
+        .context("document has no body")?;
+
+    // A WebGPU build can't switch to WebGL2 by itself, but it can hand over to a WebGL2 build
+    if !cfg!(feature = "webgl") && !webgpu_works().await {
+        let Some(url) = host.get_attribute("data-webgl-fallback") else {
+            return Err(anyhow!(
+                "WebGPU is unavailable in this browser, and the page has no WebGL2 build \
+                 to fall back to"
+            ));
+        };
+        info!("WebGPU is unavailable, loading the WebGL2 build from {url}");
+        return load_fallback(&url).await;
+    }
+
+    // e.g. `<body data-container="#stage">` to fill an element instead of a fixed-size canvas
+    let container = match host.get_attribute("data-container") {
+        Some(selector) => Some(
+            host.owner_document()
+                .context("no document")?
+                .query_selector(&selector)
+                .map_err(js_error)?
+                .with_context(|| format!("no element matches {selector:?}"))?,
+        ),
+        None => None,
+    };
+
+    let event_loop = EventLoop::new();
+    let window = try_create_window(&event_loop, container.as_ref().unwrap_or(&host))?;
+    let canvas = window.canvas();
+    let mut viewer = Viewer::new(window).await?;
+
+    let mut status = match viewer.backend_name() {
+        "WebGL2" => "Rendering with WebGL2, WebGPU is unavailable".to_owned(),
+        backend => format!("Rendering with {backend}"),
+    };
+    if !viewer.capabilities().clamp_to_border() {
+        status.push_str(", without border clamping");
+    }
+    show_status(&canvas, &status)?;
+
+    info!("loading puppet");
+    let model = PuppetSource::from_page(&host)?.load().await?;
+    viewer.load_puppet(model)?;
+
+    // e.g. `?background=chroma` for chroma keying, or `#336699` for a solid color
+    let background = query_param("background")?.or_else(|| host.get_attribute("data-background"));
+    if let Some(background) = background {
+        viewer.set_background(Background::parse(&background)?)?;
+    }
+
+    // `?hud` or `<body data-hud>` shows frame timing stats
+    if query_param("hud")?.is_some() || host.has_attribute("data-hud") {
+        viewer.set_hud(true)?;
+    }
+
+    // e.g. `<body data-max-fps="30">` to spare battery-powered devices
+    if let Some(fps) = host.get_attribute("data-max-fps") {
+        let fps = fps
+            .parse()
+            .with_context(|| format!("invalid frame rate {fps:?}"))?;
+        viewer.set_fps_cap(Some(fps))?;
+    }
+
+    let viewer = Rc::new(RefCell::new(viewer));
+    if container.is_some() {
+        let max_pixel_ratio = match host.get_attribute("data-max-pixel-ratio") {
+            Some(ratio) => ratio
+                .parse()
+                .with_context(|| format!("invalid pixel ratio {ratio:?}"))?,
+            None => canvas_fit::DEFAULT_MAX_PIXEL_RATIO,
+        };
+        canvas_fit::install(&canvas, max_pixel_ratio, viewer.clone())?;
+    }
+    dropzone::install(&canvas, viewer.clone())?;
+    viewer::spawn_event_loop(event_loop, viewer.clone());
+
+    // Once the event loop runs, for the panel and tracker to wake it up
+    viewer.borrow_mut().show_param_panel()?;
+
+    // e.g. `?tracker=ws://localhost:39540&protocol=vmc`
+    if let Some(url) = query_param("tracker")? {
+        let protocol = query_param("protocol")?;
+        let protocol = Protocol::parse(protocol.as_deref().unwrap_or("vmc"))?;
+        viewer
+            .borrow_mut()
+            .connect_tracker(&url, protocol, TrackingMapping::default())?;
+    }
+    Ok(())
+}
+
+fn try_create_window(event: &EventLoop<()>, parent: &Element) -> anyhow::Result<Window> {
+    let window = WindowBuilder::new()
+        .with_inner_size(winit::dpi::PhysicalSize::<u32>::new(1280, 720))
+        .build(event)?;
+
+    parent
+        .append_child(&window.canvas())
+        .map_err(js_error)
+        .context("couldn't append the canvas")?;
+
+    return Ok(window);
+}
+
+/// Whether WebGPU can render here, as some browsers expose the API without any adapter.
+pub async fn webgpu_works() -> bool {
+    // wgpu panics instead of failing when the API is missing altogether
+    let has_api = web_sys::window()
+        .and_then(|win| Reflect::get(&win.navigator(), &"gpu".into()).ok())
+        .map_or(false, |gpu| !gpu.is_undefined());
+
+    has_api
+        && wgpu::Instance::new(wgpu::InstanceDescriptor::default())
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .is_some()
+}
+
+/// Imports and starts another build of the viewer, from the URL of its wasm-bindgen JS module.
+async fn load_fallback(url: &str) -> anyhow::Result<()> {
+    // `import()` is syntax rather than a function, so it needs wrapping to be called from Rust
+    let import = Function::new_with_args("url", "return import(url)");
+    let module = import
+        .call1(&JsValue::NULL, &url.into())
+        .map_err(js_error)?;
+    let module = JsFuture::from(Promise::from(module))
+        .await
+        .map_err(js_error)?;
+
+    let init: Function = Reflect::get(&module, &"default".into())
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(|_| anyhow!("{url} isn't a wasm-bindgen module"))?;
+    let started = init.call0(&JsValue::NULL).map_err(js_error)?;
+    JsFuture::from(Promise::from(started))
+        .await
+        .map_err(js_error)?;
+    Ok(())
+}
+
+/// Shows a line of text below the canvas.
+fn show_status(canvas: &HtmlCanvasElement, text: &str) -> anyhow::Result<()> {
+    let document = web_sys::window()
+        .and_then(|win| win.document())
+        .context("no document")?;
+
+    let status = document.create_element("div").map_err(js_error)?;
+    status.set_class_name("inox2d-status");
+    status.set_text_content(Some(text));
+    canvas.after_with_node_1(&status).map_err(js_error)
+}
+
+/// Shows why the viewer couldn't start, at the end of the page.
+fn show_error(text: &str) -> anyhow::Result<()> {
+    let body = web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| doc.body())
+        .context("document has no body")?;
+
+    let document = body.owner_document().context("no document")?;
+    let error = document.create_element("div").map_err(js_error)?;
+    error.set_class_name("inox2d-error");
+    error.set_text_content(Some(text));
+    body.append_child(&error).map_err(js_error)?;
+    Ok(())
+}
+
+/// Whether the page asked not to spawn the default viewer, with `<body data-inox2d-manual>`.
+fn manual_mode() -> bool {
+    web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| doc.body())
+        .map_or(false, |body| body.has_attribute("data-inox2d-manual"))
+}